@@ -0,0 +1,25 @@
+use axum::{routing::get, Router};
+use axum_prom::PrometheusMetricsBuilder;
+
+#[tokio::main]
+async fn main() {
+    let (prometheus, prometheus_registry) = PrometheusMetricsBuilder::new("myapp").pair().unwrap();
+
+    // the metrics are served on an internal port only
+    let metrics_listener = tokio::net::TcpListener::bind("127.0.0.1:9090")
+        .await
+        .unwrap();
+    tokio::spawn(async move {
+        if let Err(e) = prometheus_registry.serve_listener(metrics_listener).await {
+            eprintln!("metrics endpoint failed: {e}");
+        }
+    });
+
+    let app = Router::new()
+        .route("/", get(|| async { "Hello, World!" }))
+        .layer(prometheus);
+
+    // run it with hyper on localhost:3000
+    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
+    axum::serve(listener, app).await.unwrap();
+}
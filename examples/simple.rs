@@ -15,10 +15,7 @@ async fn main() {
     let app = Router::new()
         .route("/", get(|| async { "Hello, World!" }))
         .route("/hello/{name}", get(hello))
-        .route(
-            axum_prom::DEFAULT_ENDPOINT,
-            get(|| async move { prometheus_registry.metrics() }),
-        )
+        .merge(prometheus_registry.router())
         .layer(prometheus);
 
     // run it with hyper on localhost:3000
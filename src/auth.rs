@@ -0,0 +1,119 @@
+use axum::response::{IntoResponse, Response};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use http::request::Parts;
+use http::{HeaderMap, StatusCode};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+type AuthPredicate =
+    dyn Fn(Parts) -> Pin<Box<dyn Future<Output = bool> + Send>> + Send + Sync + 'static;
+
+/// Authentication required to read the metrics endpoint
+#[derive(Clone)]
+pub(crate) enum EndpointAuth {
+    /// HTTP basic auth, with the expected base64 encoded credentials
+    Basic(String),
+    /// static bearer token
+    Bearer(String),
+    /// user-supplied predicate over the request
+    Predicate(Arc<AuthPredicate>),
+}
+
+impl EndpointAuth {
+    pub(crate) fn basic(username: &str, password: &str) -> Self {
+        EndpointAuth::Basic(STANDARD.encode(format!("{username}:{password}")))
+    }
+
+    pub(crate) fn bearer(token: &str) -> Self {
+        EndpointAuth::Bearer(token.into())
+    }
+
+    pub(crate) fn predicate<F, Fut>(predicate: F) -> Self
+    where
+        F: Fn(Parts) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        EndpointAuth::Predicate(Arc::new(move |parts| Box::pin(predicate(parts))))
+    }
+
+    /// Returns the rejection to send if the request is not authorized
+    pub(crate) async fn authorize(&self, parts: &Parts) -> Result<(), Rejection> {
+        match self {
+            EndpointAuth::Basic(expected) => {
+                check_credentials(&parts.headers, "Basic", expected, "Basic realm=\"metrics\"")
+            }
+            EndpointAuth::Bearer(expected) => {
+                check_credentials(&parts.headers, "Bearer", expected, "Bearer")
+            }
+            EndpointAuth::Predicate(predicate) => {
+                // an owned copy, so that the predicate future can hold it
+                if predicate(parts.clone()).await {
+                    Ok(())
+                } else {
+                    Err(Rejection::Forbidden)
+                }
+            }
+        }
+    }
+}
+
+/// Response sent to unauthorized scrapes
+#[derive(Debug)]
+pub(crate) enum Rejection {
+    /// missing credentials, with the `WWW-Authenticate` challenge
+    Unauthorized(&'static str),
+    /// wrong credentials
+    Forbidden,
+}
+
+impl IntoResponse for Rejection {
+    fn into_response(self) -> Response {
+        match self {
+            Rejection::Unauthorized(challenge) => {
+                (StatusCode::UNAUTHORIZED, [(WWW_AUTHENTICATE, challenge)]).into_response()
+            }
+            Rejection::Forbidden => StatusCode::FORBIDDEN.into_response(),
+        }
+    }
+}
+
+// do not leak the credentials in logs
+impl fmt::Debug for EndpointAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EndpointAuth::Basic(_) => f.write_str("Basic(..)"),
+            EndpointAuth::Bearer(_) => f.write_str("Bearer(..)"),
+            EndpointAuth::Predicate(_) => f.write_str("Predicate(..)"),
+        }
+    }
+}
+
+fn check_credentials(
+    headers: &HeaderMap,
+    scheme: &str,
+    expected: &str,
+    challenge: &'static str,
+) -> Result<(), Rejection> {
+    let credentials = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once(' '))
+        .filter(|(value_scheme, _)| value_scheme.eq_ignore_ascii_case(scheme))
+        .map(|(_, credentials)| credentials.trim());
+    match credentials {
+        None => Err(Rejection::Unauthorized(challenge)),
+        Some(credentials) if constant_time_eq(credentials.as_bytes(), expected.as_bytes()) => {
+            Ok(())
+        }
+        Some(_) => Err(Rejection::Forbidden),
+    }
+}
+
+/// Compare the credentials without leaking their content through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,156 @@
+use crate::{BodyOutcome, ResponseBodyObserver};
+use bytes::Buf;
+use http_body::{Body, Frame, SizeHint};
+use pin_project::pin_project;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Request body counting the bytes read by the inner service
+#[pin_project]
+#[derive(Debug)]
+pub struct RequestBody<B> {
+    #[pin]
+    inner: B,
+    bytes: Arc<AtomicU64>,
+}
+
+impl<B> RequestBody<B> {
+    pub(crate) fn new(inner: B, bytes: Arc<AtomicU64>) -> Self {
+        Self { inner, bytes }
+    }
+
+    /// Consumes the wrapper, returning the inner body
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B: Body> Body for RequestBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let this = self.project();
+        let frame = futures_core::ready!(this.inner.poll_frame(cx));
+        if let Some(Ok(frame)) = &frame {
+            if let Some(data) = frame.data_ref() {
+                this.bytes
+                    .fetch_add(data.remaining() as u64, Ordering::Relaxed);
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+/// Size of a request body: the `Content-Length` header when present,
+/// otherwise the bytes counted by `RequestBody`
+#[derive(Debug)]
+pub(crate) struct RequestSize {
+    content_length: Option<u64>,
+    counted: Arc<AtomicU64>,
+}
+
+impl RequestSize {
+    pub(crate) fn new(content_length: Option<u64>) -> Self {
+        Self {
+            content_length,
+            counted: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    pub(crate) fn counter(&self) -> Arc<AtomicU64> {
+        self.counted.clone()
+    }
+
+    pub(crate) fn get(&self) -> u64 {
+        self.content_length
+            .unwrap_or_else(|| self.counted.load(Ordering::Relaxed))
+    }
+}
+
+/// Response body counting the bytes sent to the client
+///
+/// The response body metrics are recorded when the body reaches its end or is dropped.
+#[pin_project]
+#[derive(Debug)]
+pub struct ResponseBody<B> {
+    #[pin]
+    inner: B,
+    /// `None` once the metrics are recorded, or if the request is not observed
+    observer: Option<ResponseBodyObserver>,
+}
+
+impl<B: Body> ResponseBody<B> {
+    pub(crate) fn new(inner: B, mut observer: Option<ResponseBodyObserver>) -> Self {
+        // hyper never polls a body already at its end, like an empty body
+        if inner.is_end_stream() {
+            if let Some(observer) = &mut observer {
+                observer.outcome = BodyOutcome::Completed;
+            }
+        }
+        Self { inner, observer }
+    }
+}
+
+impl<B> ResponseBody<B> {
+    /// Consumes the wrapper, returning the inner body
+    ///
+    /// The response body metrics are recorded with the bytes counted so far.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl<B: Body> Body for ResponseBody<B> {
+    type Data = B::Data;
+    type Error = B::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let mut this = self.project();
+        let frame = futures_core::ready!(this.inner.as_mut().poll_frame(cx));
+        if let Some(observer) = this.observer {
+            if let Some(Ok(frame)) = &frame {
+                if let Some(data) = frame.data_ref() {
+                    observer.on_data(data.remaining());
+                }
+            }
+        }
+        let outcome = match &frame {
+            None => Some(BodyOutcome::Completed),
+            Some(Err(_)) => Some(BodyOutcome::Error),
+            Some(Ok(_)) if this.inner.is_end_stream() => Some(BodyOutcome::Completed),
+            Some(Ok(_)) => None,
+        };
+        if let Some(outcome) = outcome {
+            // dropping the observer records the response body metrics
+            if let Some(mut observer) = this.observer.take() {
+                observer.outcome = outcome;
+            }
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
@@ -0,0 +1,82 @@
+use http::Method;
+use regex::Regex;
+
+/// Matches the request paths excluded from the metrics
+#[derive(Debug, Clone)]
+pub enum PathMatcher {
+    /// the path is equal to this one, like "/health"
+    Exact(String),
+    /// the path starts with this prefix, like "/internal/"
+    Prefix(String),
+    /// the path matches this regular expression, like `^/probes/(live|ready)$`
+    Regex(Regex),
+}
+
+impl PathMatcher {
+    pub fn exact(path: &str) -> Self {
+        PathMatcher::Exact(path.into())
+    }
+
+    pub fn prefix(prefix: &str) -> Self {
+        PathMatcher::Prefix(prefix.into())
+    }
+
+    /// Returns an error if `pattern` is not a valid regular expression
+    pub fn regex(pattern: &str) -> Result<Self, regex::Error> {
+        Ok(PathMatcher::Regex(Regex::new(pattern)?))
+    }
+
+    pub fn matches(&self, path: &str) -> bool {
+        match self {
+            PathMatcher::Exact(exact) => path == exact,
+            PathMatcher::Prefix(prefix) => path.starts_with(prefix.as_str()),
+            PathMatcher::Regex(regex) => regex.is_match(path),
+        }
+    }
+}
+
+/// Requests excluded from the metrics, by path or by method
+#[derive(Debug, Default)]
+pub(crate) struct Exclusions {
+    pub(crate) paths: Vec<PathMatcher>,
+    pub(crate) methods: Vec<Method>,
+}
+
+impl Exclusions {
+    pub(crate) fn excludes(&self, path: &str, method: &Method) -> bool {
+        self.methods.contains(method) || self.paths.iter().any(|matcher| matcher.matches(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_paths() {
+        assert!(PathMatcher::exact("/health").matches("/health"));
+        assert!(!PathMatcher::exact("/health").matches("/health/live"));
+        assert!(PathMatcher::prefix("/internal/").matches("/internal/debug"));
+        assert!(!PathMatcher::prefix("/internal/").matches("/internal"));
+        let regex = PathMatcher::regex("^/probes/(live|ready)$").unwrap();
+        assert!(regex.matches("/probes/live"));
+        assert!(!regex.matches("/probes/startup"));
+        assert!(PathMatcher::regex("(").is_err());
+    }
+
+    #[test]
+    fn excludes_paths_and_methods() {
+        let exclusions = Exclusions {
+            paths: vec![
+                PathMatcher::exact("/health"),
+                PathMatcher::prefix("/internal/"),
+            ],
+            methods: vec![Method::OPTIONS],
+        };
+        assert!(exclusions.excludes("/health", &Method::GET));
+        assert!(exclusions.excludes("/internal/debug", &Method::POST));
+        assert!(exclusions.excludes("/users", &Method::OPTIONS));
+        assert!(!exclusions.excludes("/users", &Method::GET));
+        assert!(!Exclusions::default().excludes("/health", &Method::OPTIONS));
+    }
+}
@@ -0,0 +1,248 @@
+use crate::series::{SeriesKeys, SeriesLabels};
+use http::request::Parts;
+use prometheus::proto::LabelPair;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// OpenMetrics limits the exemplar label set to 128 characters
+const MAX_TRACE_ID_LEN: usize = 100;
+
+type TraceIdFn = dyn Fn(&Parts) -> Option<String> + Send + Sync + 'static;
+
+/// Extracts the trace ID attached as exemplar to the request duration observations
+#[derive(Clone)]
+pub(crate) struct TraceIdExtractor(Arc<TraceIdFn>);
+
+impl TraceIdExtractor {
+    pub(crate) fn new<F>(extractor: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        Self(Arc::new(extractor))
+    }
+
+    /// Trace ID of the W3C `traceparent` header, like
+    /// "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+    pub(crate) fn traceparent() -> Self {
+        Self::new(|parts| {
+            let traceparent = parts.headers.get("traceparent")?.to_str().ok()?;
+            let mut fields = traceparent.trim().split('-');
+            let _version = fields.next()?;
+            let trace_id = fields.next()?;
+            let valid = trace_id.len() == 32
+                && trace_id.bytes().all(|b| b.is_ascii_hexdigit())
+                && trace_id.bytes().any(|b| b != b'0');
+            valid.then(|| trace_id.to_ascii_lowercase())
+        })
+    }
+
+    pub(crate) fn extract(&self, parts: &Parts) -> Option<String> {
+        (self.0)(parts).filter(|trace_id| trace_id.len() <= MAX_TRACE_ID_LEN)
+    }
+}
+
+impl fmt::Debug for TraceIdExtractor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TraceIdExtractor(..)")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Exemplar {
+    pub(crate) trace_id: String,
+    pub(crate) value: f64,
+    /// seconds since the unix epoch
+    pub(crate) timestamp: f64,
+}
+
+/// Latest exemplar of each bucket of each series of a histogram
+#[derive(Debug)]
+pub(crate) struct Exemplars {
+    /// fully qualified name of the histogram
+    name: String,
+    keys: SeriesKeys,
+    buckets: Vec<f64>,
+    route_buckets: HashMap<String, Vec<f64>>,
+    /// one exemplar per bucket, +Inf included
+    series: Mutex<HashMap<SeriesLabels, Vec<Option<Exemplar>>>>,
+}
+
+impl Exemplars {
+    pub(crate) fn new(
+        name: String,
+        keys: SeriesKeys,
+        buckets: &[f64],
+        route_buckets: &HashMap<String, Vec<f64>>,
+    ) -> Self {
+        Self {
+            name,
+            keys,
+            buckets: finite_buckets(buckets),
+            route_buckets: route_buckets
+                .iter()
+                .map(|(route, buckets)| (route.clone(), finite_buckets(buckets)))
+                .collect(),
+            series: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub(crate) fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub(crate) fn observe(&self, route: &str, label_values: &[&str], value: f64, trace_id: &str) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |now| now.as_secs_f64());
+        self.observe_at(route, label_values, value, trace_id, timestamp);
+    }
+
+    /// Record an exemplar observed at `timestamp`, in seconds since the unix epoch
+    pub(crate) fn observe_at(
+        &self,
+        route: &str,
+        label_values: &[&str],
+        value: f64,
+        trace_id: &str,
+        timestamp: f64,
+    ) {
+        let labels = self.keys.key(label_values);
+
+        let buckets = self.route_buckets.get(route).unwrap_or(&self.buckets);
+        let bucket = buckets
+            .iter()
+            .position(|upper_bound| value <= *upper_bound)
+            .unwrap_or(buckets.len());
+
+        let mut series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        let exemplars = series
+            .entry(labels)
+            .or_insert_with(|| vec![None; buckets.len() + 1]);
+        exemplars[bucket] = Some(Exemplar {
+            trace_id: trace_id.to_string(),
+            value,
+            timestamp,
+        });
+    }
+
+    /// Exemplars of the series with these label pairs, one per bucket, +Inf included
+    pub(crate) fn get(&self, labels: &[LabelPair]) -> Option<Vec<Option<Exemplar>>> {
+        let labels = SeriesKeys::metric_key(labels);
+        let series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        series.get(&labels).cloned()
+    }
+}
+
+/// Buckets really used by the histogram: like the `prometheus` crate, no bucket means
+/// the default buckets, and the +Inf bucket is ignored as it is always implicit
+fn finite_buckets(buckets: &[f64]) -> Vec<f64> {
+    if buckets.is_empty() {
+        return prometheus::DEFAULT_BUCKETS.to_vec();
+    }
+    buckets
+        .iter()
+        .copied()
+        .filter(|upper_bound| *upper_bound != f64::INFINITY)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::Request;
+
+    fn traceparent(value: &str) -> Option<String> {
+        let (parts, ()) = Request::get("/")
+            .header("traceparent", value)
+            .body(())
+            .unwrap()
+            .into_parts();
+        TraceIdExtractor::traceparent().extract(&parts)
+    }
+
+    fn exemplars(buckets: &[f64]) -> Exemplars {
+        Exemplars::new(
+            "duration".into(),
+            SeriesKeys::new(&HashMap::new(), &["endpoint"]),
+            buckets,
+            &HashMap::from([("/upload".to_string(), vec![1.0, 10.0])]),
+        )
+    }
+
+    fn buckets_with_exemplar(exemplars: &Exemplars) -> Vec<usize> {
+        let mut label = LabelPair::default();
+        label.set_name("endpoint".into());
+        label.set_value("/".into());
+        exemplars
+            .get(&[label])
+            .unwrap()
+            .iter()
+            .enumerate()
+            .filter(|(_, exemplar)| exemplar.is_some())
+            .map(|(bucket, _)| bucket)
+            .collect()
+    }
+
+    #[test]
+    fn extracts_the_traceparent_trace_id() {
+        assert_eq!(
+            traceparent("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01").as_deref(),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        assert_eq!(
+            traceparent("00-4BF92F3577B34DA6A3CE929D0E0E4736-00F067AA0BA902B7-01").as_deref(),
+            Some("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+    }
+
+    #[test]
+    fn ignores_invalid_traceparent_trace_ids() {
+        // all-zero trace ID
+        assert_eq!(
+            traceparent("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            None
+        );
+        // too short
+        assert_eq!(traceparent("00-4bf92f3577b34da6-00f067aa0ba902b7-01"), None);
+        // not hexadecimal
+        assert_eq!(
+            traceparent("00-4bf92f3577b34da6a3ce929d0e0e473z-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(traceparent("00"), None);
+        assert_eq!(traceparent(""), None);
+    }
+
+    #[test]
+    fn ignores_too_long_trace_ids() {
+        let (parts, ()) = Request::get("/").body(()).unwrap().into_parts();
+        let extractor = TraceIdExtractor::new(|_| Some("a".repeat(MAX_TRACE_ID_LEN + 1)));
+        assert_eq!(extractor.extract(&parts), None);
+    }
+
+    #[test]
+    fn selects_the_bucket_of_the_value() {
+        let exemplars = exemplars(&[0.1, 1.0, f64::INFINITY]);
+        exemplars.observe_at("/", &["/"], 0.1, "a", 0.0);
+        exemplars.observe_at("/", &["/"], 0.5, "b", 0.0);
+        exemplars.observe_at("/", &["/"], 2.0, "c", 0.0);
+        assert_eq!(buckets_with_exemplar(&exemplars), [0, 1, 2]);
+    }
+
+    #[test]
+    fn selects_the_default_buckets_without_buckets() {
+        let exemplars = exemplars(&[]);
+        exemplars.observe_at("/", &["/"], 0.031, "a", 0.0);
+        // le="0.05"
+        assert_eq!(buckets_with_exemplar(&exemplars), [3]);
+    }
+
+    #[test]
+    fn selects_the_route_buckets() {
+        let exemplars = exemplars(&[]);
+        exemplars.observe_at("/upload", &["/"], 5.0, "a", 0.0);
+        assert_eq!(buckets_with_exemplar(&exemplars), [1]);
+    }
+}
@@ -0,0 +1,370 @@
+use crate::PrometheusMetricsRegistry;
+use axum::extract::Request;
+use axum::handler::Handler;
+use axum::response::{IntoResponse, Response};
+use flate2::write::{GzEncoder, ZlibEncoder};
+use flate2::Compression;
+use http::header::{ACCEPT, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE, VARY};
+use http::request::Parts;
+use http::{HeaderMap, StatusCode};
+use prometheus::proto::MetricFamily;
+use prometheus::{Encoder, TextEncoder};
+use std::collections::HashSet;
+use std::future::Future;
+use std::io::{self, Write};
+use std::pin::Pin;
+
+/// axum handler exposing the metrics of a `PrometheusMetricsRegistry`
+///
+/// Created with `PrometheusMetricsRegistry::into_handler`
+#[derive(Debug, Clone)]
+pub struct MetricsHandler {
+    registry: PrometheusMetricsRegistry,
+}
+
+impl MetricsHandler {
+    pub(crate) fn new(registry: PrometheusMetricsRegistry) -> Self {
+        Self { registry }
+    }
+
+    fn render(&self, parts: &Parts) -> Response {
+        match self.try_render(parts) {
+            Ok(response) => response,
+            Err(err) => (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("failed to encode metrics: {err}"),
+            )
+                .into_response(),
+        }
+    }
+
+    fn try_render(&self, parts: &Parts) -> prometheus::Result<Response> {
+        let headers = &parts.headers;
+        let metric_families = self.gather(parts.uri.query());
+        let (content_type, buffer) = match Format::negotiate(headers) {
+            Format::Text => encode_with(&TextEncoder::new(), &metric_families)?,
+            Format::OpenMetrics => {
+                encode_with(&self.registry.openmetrics_encoder(), &metric_families)?
+            }
+            #[cfg(feature = "protobuf")]
+            Format::Protobuf => {
+                let mut metric_families = metric_families;
+                if let Some(native_histograms) = &self.registry.native_histograms {
+                    native_histograms.attach(&mut metric_families);
+                }
+                encode_with(&prometheus::ProtobufEncoder::new(), &metric_families)?
+            }
+        };
+
+        // the format depends on the `Accept` header
+        let Some(threshold) = self.registry.compression_threshold else {
+            return Ok((
+                [(CONTENT_TYPE, content_type), (VARY, ACCEPT.to_string())],
+                buffer,
+            )
+                .into_response());
+        };
+        let vary = format!("{ACCEPT}, {ACCEPT_ENCODING}");
+        match ContentEncoding::negotiate(headers) {
+            Some(encoding) if buffer.len() >= threshold => Ok((
+                [
+                    (CONTENT_TYPE, content_type),
+                    (CONTENT_ENCODING, encoding.as_str().to_string()),
+                    (VARY, vary),
+                ],
+                encoding.compress(&buffer)?,
+            )
+                .into_response()),
+            _ => Ok(([(CONTENT_TYPE, content_type), (VARY, vary)], buffer).into_response()),
+        }
+    }
+
+    /// Gather the metric families, keeping only the ones named in the
+    /// `name[]` query parameters if any
+    fn gather(&self, query: Option<&str>) -> Vec<MetricFamily> {
+        let metric_families = self.registry.registry.gather();
+        let names: HashSet<String> = form_urlencoded::parse(query.unwrap_or_default().as_bytes())
+            .filter(|(key, _)| key == "name[]")
+            .map(|(_, value)| value.into_owned())
+            .collect();
+        if names.is_empty() {
+            metric_families
+        } else {
+            metric_families
+                .into_iter()
+                .filter(|mf| names.contains(mf.get_name()))
+                .collect()
+        }
+    }
+}
+
+fn encode_with<E: Encoder>(
+    encoder: &E,
+    metric_families: &[MetricFamily],
+) -> prometheus::Result<(String, Vec<u8>)> {
+    let mut buffer = vec![];
+    encoder.encode(metric_families, &mut buffer)?;
+    Ok((encoder.format_type().to_string(), buffer))
+}
+
+impl<S> Handler<(), S> for MetricsHandler
+where
+    S: Send + Sync + 'static,
+{
+    type Future = Pin<Box<dyn Future<Output = Response> + Send>>;
+
+    fn call(self, req: Request, _state: S) -> Self::Future {
+        Box::pin(async move {
+            let (parts, _body) = req.into_parts();
+            if let Some(auth) = &self.registry.auth {
+                if let Err(rejection) = auth.authorize(&parts).await {
+                    return rejection.into_response();
+                }
+            }
+            self.render(&parts)
+        })
+    }
+}
+
+/// Exposition formats supported by the metrics handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Text,
+    OpenMetrics,
+    #[cfg(feature = "protobuf")]
+    Protobuf,
+}
+
+impl Format {
+    /// Select the format with the highest quality in the `Accept` header,
+    /// falling back to the text format
+    fn negotiate(headers: &HeaderMap) -> Self {
+        let mut selected = (Format::Text, 0.0);
+        for value in headers.get_all(ACCEPT) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for media_range in value.split(',') {
+                let mut params = media_range.split(';').map(str::trim);
+                let media_type = params.next().unwrap_or_default();
+                let params: Vec<&str> = params.collect();
+                let format = match media_type {
+                    "application/openmetrics-text" => Format::OpenMetrics,
+                    "text/plain" => Format::Text,
+                    #[cfg(feature = "protobuf")]
+                    "application/vnd.google.protobuf"
+                        if params.contains(&"proto=io.prometheus.client.MetricFamily")
+                            && params.iter().all(|param| {
+                                !param.starts_with("encoding=") || *param == "encoding=delimited"
+                            }) =>
+                    {
+                        Format::Protobuf
+                    }
+                    _ => continue,
+                };
+                let quality = params
+                    .iter()
+                    .find_map(|param| param.strip_prefix("q="))
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                if quality > selected.1 {
+                    selected = (format, quality);
+                }
+            }
+        }
+        selected.0
+    }
+}
+
+/// Compressions of the metrics payload supported by the metrics handler
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ContentEncoding {
+    Gzip,
+    Deflate,
+}
+
+impl ContentEncoding {
+    /// Select the encoding with the highest quality in the `Accept-Encoding` header,
+    /// preferring gzip on equal quality
+    ///
+    /// Like RFC 9110, `*` only applies to the codings not listed explicitly,
+    /// and a coding with a `q=0` quality is refused.
+    fn negotiate(headers: &HeaderMap) -> Option<Self> {
+        let mut gzip = None;
+        let mut deflate = None;
+        let mut wildcard = None;
+        for value in headers.get_all(ACCEPT_ENCODING) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for coding in value.split(',') {
+                let mut params = coding.split(';').map(str::trim);
+                let coding = params.next().unwrap_or_default().to_ascii_lowercase();
+                let quality = params
+                    .find_map(|param| param.strip_prefix("q="))
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                match coding.as_str() {
+                    "gzip" | "x-gzip" => gzip = Some(quality),
+                    "deflate" => deflate = Some(quality),
+                    "*" => wildcard = Some(quality),
+                    _ => {}
+                }
+            }
+        }
+        let gzip = gzip.or(wildcard).unwrap_or(0.0);
+        let deflate = deflate.or(wildcard).unwrap_or(0.0);
+        if gzip > 0.0 && gzip >= deflate {
+            Some(ContentEncoding::Gzip)
+        } else if deflate > 0.0 {
+            Some(ContentEncoding::Deflate)
+        } else {
+            None
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            ContentEncoding::Gzip => "gzip",
+            ContentEncoding::Deflate => "deflate",
+        }
+    }
+
+    fn compress(self, buffer: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            ContentEncoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(buffer)?;
+                encoder.finish()
+            }
+            ContentEncoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(buffer)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    fn accept(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT, HeaderValue::from_static(value));
+        headers
+    }
+
+    fn accept_encoding(value: &'static str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(ACCEPT_ENCODING, HeaderValue::from_static(value));
+        headers
+    }
+
+    #[test]
+    fn negotiates_text_by_default() {
+        assert_eq!(Format::negotiate(&HeaderMap::new()), Format::Text);
+        assert_eq!(Format::negotiate(&accept("*/*")), Format::Text);
+        assert_eq!(Format::negotiate(&accept("application/json")), Format::Text);
+        assert_eq!(
+            Format::negotiate(&accept("text/plain;version=0.0.4")),
+            Format::Text
+        );
+    }
+
+    #[test]
+    fn negotiates_openmetrics() {
+        assert_eq!(
+            Format::negotiate(&accept("application/openmetrics-text")),
+            Format::OpenMetrics
+        );
+        assert_eq!(
+            Format::negotiate(&accept(
+                "text/plain;version=0.0.4;q=0.9, application/openmetrics-text;version=1.0.0"
+            )),
+            Format::OpenMetrics
+        );
+        assert_eq!(
+            Format::negotiate(&accept(
+                "application/openmetrics-text;version=1.0.0;q=0.2, text/plain;version=0.0.4"
+            )),
+            Format::Text
+        );
+    }
+
+    #[test]
+    fn negotiates_prometheus_default_accept() {
+        // Prometheus 2.x
+        assert_eq!(
+            Format::negotiate(&accept(
+                "application/openmetrics-text;version=1.0.0;q=0.5,\
+                 application/openmetrics-text;version=0.0.1;q=0.4,\
+                 text/plain;version=0.0.4;q=0.3,*/*;q=0.2"
+            )),
+            Format::OpenMetrics
+        );
+        // Prometheus 3.x
+        assert_eq!(
+            Format::negotiate(&accept(
+                "application/openmetrics-text;version=1.0.0;escaping=allow-utf-8;q=0.5,\
+                 application/openmetrics-text;version=0.0.1;q=0.4,\
+                 text/plain;version=1.0.0;escaping=allow-utf-8;q=0.3,\
+                 text/plain;version=0.0.4;q=0.2,*/*;q=0.1"
+            )),
+            Format::OpenMetrics
+        );
+        // native histograms enabled: protobuf first
+        let protobuf_first = accept(
+            "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;\
+             encoding=delimited;q=0.5,\
+             application/openmetrics-text;version=1.0.0;q=0.4,\
+             application/openmetrics-text;version=0.0.1;q=0.3,\
+             text/plain;version=0.0.4;q=0.2,*/*;q=0.1",
+        );
+        #[cfg(feature = "protobuf")]
+        assert_eq!(Format::negotiate(&protobuf_first), Format::Protobuf);
+        #[cfg(not(feature = "protobuf"))]
+        assert_eq!(Format::negotiate(&protobuf_first), Format::OpenMetrics);
+    }
+
+    #[test]
+    fn ignores_other_protobuf_encodings() {
+        assert_eq!(
+            Format::negotiate(&accept(
+                "application/vnd.google.protobuf;proto=io.prometheus.client.MetricFamily;\
+                 encoding=text;q=0.9, text/plain;q=0.1"
+            )),
+            Format::Text
+        );
+    }
+
+    #[test]
+    fn negotiates_content_encoding() {
+        let negotiate = |value| ContentEncoding::negotiate(&accept_encoding(value));
+        assert_eq!(ContentEncoding::negotiate(&HeaderMap::new()), None);
+        assert_eq!(negotiate("identity"), None);
+        assert_eq!(negotiate("gzip"), Some(ContentEncoding::Gzip));
+        assert_eq!(negotiate("x-gzip"), Some(ContentEncoding::Gzip));
+        assert_eq!(negotiate("deflate"), Some(ContentEncoding::Deflate));
+        assert_eq!(negotiate("deflate, gzip"), Some(ContentEncoding::Gzip));
+        assert_eq!(
+            negotiate("gzip;q=0.5, deflate"),
+            Some(ContentEncoding::Deflate)
+        );
+        assert_eq!(negotiate("GZIP;q=0.8"), Some(ContentEncoding::Gzip));
+        assert_eq!(negotiate("*"), Some(ContentEncoding::Gzip));
+    }
+
+    #[test]
+    fn honors_refused_content_encodings() {
+        let negotiate = |value| ContentEncoding::negotiate(&accept_encoding(value));
+        assert_eq!(negotiate("gzip;q=0"), None);
+        assert_eq!(negotiate("gzip;q=0, *"), Some(ContentEncoding::Deflate));
+        assert_eq!(negotiate("*, gzip;q=0"), Some(ContentEncoding::Deflate));
+        assert_eq!(negotiate("gzip;q=0, deflate;q=0, *"), None);
+        assert_eq!(negotiate("*;q=0"), None);
+        assert_eq!(negotiate("gzip, *;q=0"), Some(ContentEncoding::Gzip));
+    }
+}
@@ -0,0 +1,363 @@
+use http::{request, response};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+
+/// Adds label dimensions to the request metrics, computed from the request and its response
+///
+/// The labels are added to every request metric but `http_requests_in_flight`.
+/// A label without value is recorded with an empty value.
+pub trait LabelExtractor: Send + Sync + 'static {
+    /// Names of the extracted labels, which cannot be changed afterwards
+    fn label_names(&self) -> Vec<String>;
+
+    /// Set the labels known from the request, like a tenant ID header
+    fn request_labels(&self, _request: &request::Parts, _labels: &mut HashMap<String, String>) {}
+
+    /// Set the labels known from the response, like a cache hit header
+    ///
+    /// Not called when the inner service returns an error or the response future is dropped.
+    fn response_labels(&self, _response: &response::Parts, _labels: &mut HashMap<String, String>) {}
+}
+
+/// Names of the default labels of the request metrics, `None` when disabled
+#[derive(Debug, Clone)]
+pub(crate) struct LabelNames {
+    pub(crate) endpoint: Option<String>,
+    pub(crate) method: Option<String>,
+    pub(crate) status: Option<String>,
+}
+
+impl Default for LabelNames {
+    fn default() -> Self {
+        Self {
+            endpoint: Some("endpoint".into()),
+            method: Some("method".into()),
+            status: Some("status".into()),
+        }
+    }
+}
+
+impl LabelNames {
+    /// Names of the enabled labels
+    pub(crate) fn names(&self) -> Vec<&str> {
+        [&self.endpoint, &self.method, &self.status]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Names of the enabled labels of `http_requests_in_flight`, without status
+    pub(crate) fn in_flight_names(&self) -> Vec<&str> {
+        [&self.endpoint, &self.method]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect()
+    }
+
+    /// Values of the enabled labels, in the order of their names
+    pub(crate) fn values<'a>(
+        &self,
+        endpoint: &'a str,
+        method: &'a str,
+        status: Option<&'a str>,
+    ) -> Vec<&'a str> {
+        [
+            (&self.endpoint, Some(endpoint)),
+            (&self.method, Some(method)),
+            (&self.status, status),
+        ]
+        .into_iter()
+        .filter(|(name, _)| name.is_some())
+        .filter_map(|(_, value)| value)
+        .collect()
+    }
+}
+
+/// Labels set by a handler, inserted in the response extensions
+///
+/// Only the labels declared with `PrometheusMetricsBuilder::handler_labels` are recorded.
+///
+/// Example: `(Extension(MetricLabels::new().with("operation", "checkout")), body)`
+#[derive(Debug, Clone, Default)]
+pub struct MetricLabels(HashMap<String, String>);
+
+impl MetricLabels {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the value of a label
+    #[must_use]
+    pub fn with(mut self, name: &str, value: &str) -> Self {
+        self.insert(name, value);
+        self
+    }
+
+    /// Set the value of a label
+    pub fn insert(&mut self, name: &str, value: &str) {
+        self.0.insert(name.into(), value.into());
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.0.get(name).map(String::as_str)
+    }
+}
+
+/// Label dimensions added to the request metrics
+#[derive(Clone)]
+pub(crate) struct ExtraLabels {
+    extractor: Option<Arc<dyn LabelExtractor>>,
+    /// labels taken from the `MetricLabels` of the responses
+    handler_labels: HashSet<String>,
+    names: Vec<String>,
+}
+
+impl ExtraLabels {
+    /// Returns `None` if no label is added
+    pub(crate) fn new(
+        extractor: Option<Arc<dyn LabelExtractor>>,
+        handler_labels: Vec<String>,
+    ) -> Option<Self> {
+        let mut names = extractor
+            .as_ref()
+            .map(|extractor| extractor.label_names())
+            .unwrap_or_default();
+        for name in &handler_labels {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        (!names.is_empty()).then(|| Self {
+            extractor,
+            handler_labels: handler_labels.into_iter().collect(),
+            names,
+        })
+    }
+
+    pub(crate) fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    pub(crate) fn request_labels(&self, request: &request::Parts) -> HashMap<String, String> {
+        let mut labels = HashMap::new();
+        if let Some(extractor) = &self.extractor {
+            extractor.request_labels(request, &mut labels);
+        }
+        labels
+    }
+
+    /// Complete the labels with the ones of the response, the handler labels
+    /// overriding the extracted ones
+    pub(crate) fn response_labels(
+        &self,
+        response: &response::Parts,
+        labels: &mut HashMap<String, String>,
+    ) {
+        if let Some(extractor) = &self.extractor {
+            extractor.response_labels(response, labels);
+        }
+        if let Some(MetricLabels(handler_labels)) = response.extensions.get::<MetricLabels>() {
+            for (name, value) in handler_labels {
+                if self.handler_labels.contains(name) {
+                    labels.insert(name.clone(), value.clone());
+                }
+            }
+        }
+    }
+
+    /// Label values in the order of the label names, ignoring the unknown labels
+    pub(crate) fn values(&self, mut labels: HashMap<String, String>) -> Vec<String> {
+        self.names
+            .iter()
+            .map(|name| labels.remove(name).unwrap_or_default())
+            .collect()
+    }
+}
+
+impl fmt::Debug for ExtraLabels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExtraLabels")
+            .field("handler_labels", &self.handler_labels)
+            .field("names", &self.names)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::{Request, Response};
+
+    struct TenantExtractor;
+
+    impl LabelExtractor for TenantExtractor {
+        fn label_names(&self) -> Vec<String> {
+            vec!["tenant".into(), "cache".into()]
+        }
+
+        fn request_labels(&self, request: &request::Parts, labels: &mut HashMap<String, String>) {
+            if let Some(tenant) = request.headers.get("x-tenant") {
+                labels.insert("tenant".into(), tenant.to_str().unwrap().into());
+            }
+            // not a declared label
+            labels.insert("user".into(), "alice".into());
+        }
+
+        fn response_labels(
+            &self,
+            response: &response::Parts,
+            labels: &mut HashMap<String, String>,
+        ) {
+            if let Some(cache) = response.headers.get("x-cache") {
+                labels.insert("cache".into(), cache.to_str().unwrap().into());
+            }
+        }
+    }
+
+    fn request(tenant: Option<&str>) -> request::Parts {
+        let mut request = Request::get("/");
+        if let Some(tenant) = tenant {
+            request = request.header("x-tenant", tenant);
+        }
+        request.body(()).unwrap().into_parts().0
+    }
+
+    fn response(cache: Option<&str>, metric_labels: Option<MetricLabels>) -> response::Parts {
+        let mut response = Response::builder();
+        if let Some(cache) = cache {
+            response = response.header("x-cache", cache);
+        }
+        if let Some(metric_labels) = metric_labels {
+            response = response.extension(metric_labels);
+        }
+        response.body(()).unwrap().into_parts().0
+    }
+
+    fn extracted_values(
+        extra_labels: &ExtraLabels,
+        request: request::Parts,
+        response: response::Parts,
+    ) -> Vec<String> {
+        let mut labels = extra_labels.request_labels(&request);
+        extra_labels.response_labels(&response, &mut labels);
+        extra_labels.values(labels)
+    }
+
+    fn label_names(
+        endpoint: Option<&str>,
+        method: Option<&str>,
+        status: Option<&str>,
+    ) -> LabelNames {
+        LabelNames {
+            endpoint: endpoint.map(String::from),
+            method: method.map(String::from),
+            status: status.map(String::from),
+        }
+    }
+
+    #[test]
+    fn names_the_default_labels() {
+        let names = LabelNames::default();
+        assert_eq!(names.names(), ["endpoint", "method", "status"]);
+        assert_eq!(names.in_flight_names(), ["endpoint", "method"]);
+        assert_eq!(names.values("/", "GET", Some("200")), ["/", "GET", "200"]);
+        assert_eq!(names.values("/", "GET", None), ["/", "GET"]);
+    }
+
+    #[test]
+    fn renames_the_default_labels() {
+        let names = label_names(Some("route"), Some("http_method"), Some("code"));
+        assert_eq!(names.names(), ["route", "http_method", "code"]);
+        assert_eq!(names.in_flight_names(), ["route", "http_method"]);
+        assert_eq!(names.values("/", "GET", Some("200")), ["/", "GET", "200"]);
+    }
+
+    #[test]
+    fn disables_each_default_label() {
+        let names = label_names(None, Some("method"), Some("status"));
+        assert_eq!(names.names(), ["method", "status"]);
+        assert_eq!(names.in_flight_names(), ["method"]);
+        assert_eq!(names.values("/", "GET", Some("200")), ["GET", "200"]);
+        assert_eq!(names.values("/", "GET", None), ["GET"]);
+
+        let names = label_names(Some("endpoint"), None, Some("status"));
+        assert_eq!(names.names(), ["endpoint", "status"]);
+        assert_eq!(names.in_flight_names(), ["endpoint"]);
+        assert_eq!(names.values("/", "GET", Some("200")), ["/", "200"]);
+
+        let names = label_names(Some("endpoint"), Some("method"), None);
+        assert_eq!(names.names(), ["endpoint", "method"]);
+        assert_eq!(names.in_flight_names(), ["endpoint", "method"]);
+        assert_eq!(names.values("/", "GET", Some("200")), ["/", "GET"]);
+
+        let names = label_names(None, None, None);
+        assert!(names.names().is_empty());
+        assert!(names.values("/", "GET", Some("200")).is_empty());
+    }
+
+    #[test]
+    fn adds_no_label_by_default() {
+        assert!(ExtraLabels::new(None, vec![]).is_none());
+    }
+
+    #[test]
+    fn extracts_labels_from_the_request_and_the_response() {
+        let extra_labels = ExtraLabels::new(Some(Arc::new(TenantExtractor)), vec![]).unwrap();
+        assert_eq!(extra_labels.names(), ["tenant", "cache"]);
+
+        let values = extracted_values(
+            &extra_labels,
+            request(Some("acme")),
+            response(Some("hit"), None),
+        );
+        assert_eq!(values, ["acme", "hit"]);
+    }
+
+    #[test]
+    fn records_missing_labels_as_empty() {
+        let extra_labels = ExtraLabels::new(Some(Arc::new(TenantExtractor)), vec![]).unwrap();
+
+        let values = extracted_values(&extra_labels, request(None), response(None, None));
+        assert_eq!(values, ["", ""]);
+    }
+
+    #[test]
+    fn handler_labels_override_the_extracted_ones() {
+        let extra_labels = ExtraLabels::new(
+            Some(Arc::new(TenantExtractor)),
+            vec!["cache".into(), "operation".into()],
+        )
+        .unwrap();
+        // a handler label is declared once
+        assert_eq!(extra_labels.names(), ["tenant", "cache", "operation"]);
+
+        let metric_labels = MetricLabels::new()
+            .with("cache", "miss")
+            .with("operation", "checkout");
+        let values = extracted_values(
+            &extra_labels,
+            request(Some("acme")),
+            response(Some("hit"), Some(metric_labels)),
+        );
+        assert_eq!(values, ["acme", "miss", "checkout"]);
+    }
+
+    #[test]
+    fn ignores_undeclared_handler_labels() {
+        let extra_labels = ExtraLabels::new(None, vec!["operation".into()]).unwrap();
+
+        let metric_labels = MetricLabels::new()
+            .with("operation", "checkout")
+            .with("user", "alice");
+        let values = extracted_values(
+            &extra_labels,
+            request(None),
+            response(None, Some(metric_labels)),
+        );
+        assert_eq!(values, ["checkout"]);
+    }
+}
@@ -1,25 +1,117 @@
+mod auth;
+mod body;
+mod exclude;
+mod exemplars;
+mod handler;
+mod labels;
+#[cfg(feature = "protobuf")]
+mod native_histogram;
+mod openmetrics;
+mod route_histogram;
+mod series;
+mod summary;
+
+pub use body::{RequestBody, ResponseBody};
+pub use exclude::PathMatcher;
+pub use handler::MetricsHandler;
+pub use labels::{LabelExtractor, MetricLabels};
+pub use openmetrics::{OpenMetricsEncoder, OPENMETRICS_FORMAT};
+pub use route_histogram::RouteHistogramVec;
+pub use summary::{
+    DurationMetric, SummaryVec, DEFAULT_SUMMARY_AGE_BUCKETS, DEFAULT_SUMMARY_MAX_AGE,
+    DEFAULT_SUMMARY_QUANTILES,
+};
+
+use crate::auth::EndpointAuth;
+use crate::body::RequestSize;
+use crate::exclude::Exclusions;
+use crate::exemplars::{Exemplars, TraceIdExtractor};
+use crate::labels::{ExtraLabels, LabelNames};
+#[cfg(feature = "protobuf")]
+use crate::native_histogram::NativeHistograms;
+use crate::series::SeriesKeys;
+use crate::summary::SummaryOpts;
 use axum::extract::MatchedPath;
+use axum::routing::get;
+use axum::Router;
+use http::header::CONTENT_LENGTH;
+use http::request::Parts;
 use http::{Method, Request, Response, StatusCode};
-use pin_project::pin_project;
+use pin_project::{pin_project, pinned_drop};
+use prometheus::core::Collector;
 use prometheus::{
-    Encoder, HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry, TextEncoder,
+    Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry,
+    TextEncoder,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::future::Future;
+use std::io;
 use std::pin::Pin;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, PoisonError};
 use std::task::{Context, Poll};
-use std::time::Instant;
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::{TcpListener, ToSocketAddrs};
 use tower::{Layer, Service};
 
 pub const DEFAULT_ENDPOINT: &str = "/metrics";
 
+/// Default buckets for body size histograms, from 64 bytes to 16 MiB
+pub const DEFAULT_SIZE_BUCKETS: &[f64; 10] = &[
+    64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0, 4194304.0, 16777216.0,
+];
+
+/// Status label used when the inner service returns an error instead of a response
+pub const ERROR_STATUS: &str = "error";
+
+/// Endpoint label used for requests that did not match any route (404s, fallbacks)
+pub const UNMATCHED_ENDPOINT: &str = "<unmatched>";
+
+/// Label value used for every label of a series exceeding `max_series`
+pub const OVERFLOW_LABEL: &str = "__overflow__";
+
+/// Default status label used when the response future is dropped before completion
+pub const DEFAULT_CANCELLED_STATUS: &str = "cancelled";
+
+/// Method label used for the non-standard HTTP methods, when normalized
+pub const OTHER_METHOD: &str = "OTHER";
+
+/// Methods defined by RFC 9110 and RFC 5789, recorded as is
+const STANDARD_METHODS: [Method; 9] = [
+    Method::GET,
+    Method::HEAD,
+    Method::POST,
+    Method::PUT,
+    Method::DELETE,
+    Method::CONNECT,
+    Method::OPTIONS,
+    Method::TRACE,
+    Method::PATCH,
+];
+
 pub struct PrometheusMetricsBuilder {
     namespace: String,
     endpoint: Option<String>,
     const_labels: HashMap<String, String>,
     registry: Registry,
     buckets: Vec<f64>,
+    route_buckets: HashMap<String, Vec<f64>>,
+    summary: Option<SummaryOpts>,
+    size_buckets: Vec<f64>,
+    cancelled_status: String,
+    collapse_unmatched_paths: bool,
+    normalize_methods: bool,
+    allowed_methods: Vec<Method>,
+    label_names: LabelNames,
+    exact_status_codes: Option<Vec<StatusCode>>,
+    exclusions: Exclusions,
+    max_series: Option<usize>,
+    compression_threshold: Option<usize>,
+    auth: Option<EndpointAuth>,
+    trace_id_extractor: Option<TraceIdExtractor>,
+    label_extractor: Option<Arc<dyn LabelExtractor>>,
+    handler_labels: Vec<String>,
+    #[cfg(feature = "protobuf")]
+    native_histogram_bucket_factor: Option<f64>,
 }
 
 impl PrometheusMetricsBuilder {
@@ -34,6 +126,24 @@ impl PrometheusMetricsBuilder {
             const_labels: HashMap::new(),
             registry: Registry::new(),
             buckets: prometheus::DEFAULT_BUCKETS.to_vec(),
+            route_buckets: HashMap::new(),
+            summary: None,
+            size_buckets: DEFAULT_SIZE_BUCKETS.to_vec(),
+            cancelled_status: DEFAULT_CANCELLED_STATUS.into(),
+            collapse_unmatched_paths: true,
+            normalize_methods: true,
+            allowed_methods: vec![],
+            label_names: LabelNames::default(),
+            exact_status_codes: None,
+            exclusions: Exclusions::default(),
+            max_series: None,
+            compression_threshold: None,
+            auth: None,
+            trace_id_extractor: None,
+            label_extractor: None,
+            handler_labels: vec![],
+            #[cfg(feature = "protobuf")]
+            native_histogram_bucket_factor: None,
         }
     }
 
@@ -53,6 +163,257 @@ impl PrometheusMetricsBuilder {
         self
     }
 
+    /// Set the duration histogram buckets of a route, overriding `buckets`
+    ///
+    /// The route is the matched path, with its placeholders.
+    /// Ignored when the endpoint label is disabled.
+    ///
+    /// Example: `route_buckets("/upload", &[1.0, 5.0, 30.0])`
+    #[must_use]
+    pub fn route_buckets(mut self, route: &str, value: &[f64]) -> Self {
+        self.route_buckets.insert(route.into(), value.to_vec());
+        self
+    }
+
+    /// Record the request durations as a summary with client-side quantiles
+    /// instead of a histogram
+    ///
+    /// The quantiles, like `DEFAULT_SUMMARY_QUANTILES`, are computed over a sliding window
+    /// of `max_age`, split in `age_buckets` buckets rotated every `max_age / age_buckets`.
+    /// Quantiles cannot be aggregated across instances: prefer histograms when possible.
+    /// Exemplars and native histograms are only recorded with histograms.
+    /// `pair` returns an error if a quantile is not in [0, 1],
+    /// or if `max_age` is too short to be split in `age_buckets`, like zero.
+    #[must_use]
+    pub fn summary(mut self, quantiles: &[f64], max_age: Duration, age_buckets: u32) -> Self {
+        self.summary = Some(SummaryOpts {
+            quantiles: quantiles.to_vec(),
+            max_age,
+            age_buckets,
+        });
+        self
+    }
+
+    /// Set the status label recorded for requests whose response future is dropped
+    /// before completion (client disconnect, timeout...)
+    ///
+    /// Example: "cancelled" (default) or "499"
+    #[must_use]
+    pub fn cancelled_status(mut self, value: &str) -> Self {
+        self.cancelled_status = value.into();
+        self
+    }
+
+    /// Record requests that did not match any route under the `UNMATCHED_ENDPOINT` endpoint label
+    ///
+    /// Enabled by default. When disabled, the raw request path is used as endpoint label,
+    /// which can create an unbounded number of series (scanners hitting random URLs...)
+    #[must_use]
+    pub fn collapse_unmatched_paths(mut self, value: bool) -> Self {
+        self.collapse_unmatched_paths = value;
+        self
+    }
+
+    /// Record the non-standard HTTP methods under the `OTHER_METHOD` method label
+    ///
+    /// Enabled by default. When disabled, any method sent by clients is used as method label,
+    /// which can create an unbounded number of series.
+    #[must_use]
+    pub fn normalize_methods(mut self, value: bool) -> Self {
+        self.normalize_methods = value;
+        self
+    }
+
+    /// Record these non-standard HTTP methods as is, when the methods are normalized
+    ///
+    /// Example: `allow_methods(&[Method::from_bytes(b"PROPFIND")?])`
+    #[must_use]
+    pub fn allow_methods(mut self, value: &[Method]) -> Self {
+        self.allowed_methods.extend_from_slice(value);
+        self
+    }
+
+    /// Rename the `endpoint` label of the request metrics, or disable it with `None`
+    ///
+    /// Example: `endpoint_label(Some("route"))`
+    #[must_use]
+    pub fn endpoint_label(mut self, value: Option<&str>) -> Self {
+        self.label_names.endpoint = value.map(|s| s.into());
+        self
+    }
+
+    /// Rename the `method` label of the request metrics, or disable it with `None`
+    ///
+    /// Example: `method_label(Some("http_method"))`
+    #[must_use]
+    pub fn method_label(mut self, value: Option<&str>) -> Self {
+        self.label_names.method = value.map(|s| s.into());
+        self
+    }
+
+    /// Rename the `status` label of the request metrics, or disable it with `None`
+    ///
+    /// Example: `status_label(Some("code"))`
+    #[must_use]
+    pub fn status_label(mut self, value: Option<&str>) -> Self {
+        self.label_names.status = value.map(|s| s.into());
+        self
+    }
+
+    /// Record the status codes by class, like `2xx` or `4xx`, except the ones of `exact`
+    ///
+    /// Example: `group_status_codes(&[StatusCode::TOO_MANY_REQUESTS, StatusCode::SERVICE_UNAVAILABLE])`
+    /// records "429", "503", "2xx", "4xx", "5xx"...
+    #[must_use]
+    pub fn group_status_codes(mut self, exact: &[StatusCode]) -> Self {
+        self.exact_status_codes = Some(exact.to_vec());
+        self
+    }
+
+    /// Do not record the requests whose path matches one of these matchers
+    ///
+    /// The matchers apply to the raw request path, like the metrics `endpoint`.
+    ///
+    /// Example: `exclude_paths([PathMatcher::exact("/health"), PathMatcher::prefix("/internal/")])`
+    #[must_use]
+    pub fn exclude_paths(mut self, value: impl IntoIterator<Item = PathMatcher>) -> Self {
+        self.exclusions.paths.extend(value);
+        self
+    }
+
+    /// Do not record the requests with these methods
+    ///
+    /// Example: `exclude_methods(&[Method::OPTIONS, Method::HEAD])`
+    #[must_use]
+    pub fn exclude_methods(mut self, value: &[Method]) -> Self {
+        self.exclusions.methods.extend_from_slice(value);
+        self
+    }
+
+    /// Set the maximum number of distinct label combinations per metric
+    ///
+    /// Once reached, new label combinations are recorded in a series where every label
+    /// is set to `OVERFLOW_LABEL`, and the `label_overflow_total` counter is incremented.
+    /// By default, the number of series is not limited.
+    #[must_use]
+    pub fn max_series(mut self, value: usize) -> Self {
+        self.max_series = Some(value);
+        self
+    }
+
+    /// Set body size histogram buckets
+    #[must_use]
+    pub fn size_buckets(mut self, value: &[f64]) -> Self {
+        self.size_buckets = value.to_vec();
+        self
+    }
+
+    /// Compress the metrics payload with gzip or deflate, as accepted by the scraper,
+    /// when it is at least `value` bytes long
+    ///
+    /// By default, the metrics payload is not compressed.
+    #[must_use]
+    pub fn compression_threshold(mut self, value: usize) -> Self {
+        self.compression_threshold = Some(value);
+        self
+    }
+
+    /// Protect the metrics endpoint with HTTP basic auth
+    ///
+    /// Requests without credentials get a 401, requests with wrong credentials get a 403.
+    /// Replaces any authentication previously set.
+    #[must_use]
+    pub fn basic_auth(mut self, username: &str, password: &str) -> Self {
+        self.auth = Some(EndpointAuth::basic(username, password));
+        self
+    }
+
+    /// Protect the metrics endpoint with a static bearer token
+    ///
+    /// Requests without token get a 401, requests with a wrong token get a 403.
+    /// Replaces any authentication previously set.
+    #[must_use]
+    pub fn bearer_token(mut self, token: &str) -> Self {
+        self.auth = Some(EndpointAuth::bearer(token));
+        self
+    }
+
+    /// Protect the metrics endpoint with an async predicate over the request
+    ///
+    /// The predicate receives a copy of the request parts, that its future can hold,
+    /// like `|parts| async move { check(parts.headers.get("x-api-key")).await }`.
+    /// Requests for which the predicate returns `false` get a 403.
+    /// Replaces any authentication previously set.
+    #[must_use]
+    pub fn authorize<F, Fut>(mut self, predicate: F) -> Self
+    where
+        F: Fn(Parts) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = bool> + Send + 'static,
+    {
+        self.auth = Some(EndpointAuth::predicate(predicate));
+        self
+    }
+
+    /// Attach exemplars with the trace ID of the W3C `traceparent` request header
+    /// to the request duration observations
+    ///
+    /// The exemplars are exposed in the OpenMetrics format.
+    #[must_use]
+    pub fn exemplars(mut self, value: bool) -> Self {
+        self.trace_id_extractor = value.then(TraceIdExtractor::traceparent);
+        self
+    }
+
+    /// Attach exemplars with the trace ID returned by `extractor`
+    /// to the request duration observations
+    ///
+    /// The exemplars are exposed in the OpenMetrics format.
+    /// Replaces the extraction of the `traceparent` header set by `exemplars`.
+    #[must_use]
+    pub fn exemplar_extractor<F>(mut self, extractor: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        self.trace_id_extractor = Some(TraceIdExtractor::new(extractor));
+        self
+    }
+
+    /// Also record the request durations as a Prometheus native histogram,
+    /// with sparse exponential buckets
+    ///
+    /// Each bucket is at most `bucket_factor` times larger than the previous one,
+    /// like 1.1 for a 10% resolution. The native histogram is exposed in the protobuf format,
+    /// along with the classic buckets.
+    /// `pair` returns an error if `bucket_factor` is not a finite number above 1.
+    #[cfg(feature = "protobuf")]
+    #[must_use]
+    pub fn native_histograms(mut self, bucket_factor: f64) -> Self {
+        self.native_histogram_bucket_factor = Some(bucket_factor);
+        self
+    }
+
+    /// Add the labels computed by `extractor` from the requests and their responses
+    /// to the request metrics
+    ///
+    /// Replaces any label extractor previously set.
+    #[must_use]
+    pub fn label_extractor<E: LabelExtractor>(mut self, extractor: E) -> Self {
+        self.label_extractor = Some(Arc::new(extractor));
+        self
+    }
+
+    /// Declare the labels that handlers can set with a `MetricLabels` in the response extensions
+    ///
+    /// The labels are added to the request metrics, with an empty value
+    /// when not set by the handler. Other labels of the `MetricLabels` are ignored.
+    ///
+    /// Example: `handler_labels(&["operation"])`
+    #[must_use]
+    pub fn handler_labels(mut self, value: &[&str]) -> Self {
+        self.handler_labels = value.iter().map(|name| name.to_string()).collect();
+        self
+    }
+
     /// Set labels to add on every metrics
     #[must_use]
     pub fn const_labels(mut self, value: HashMap<String, String>) -> Self {
@@ -73,13 +434,26 @@ impl PrometheusMetricsBuilder {
     pub fn pair(
         self,
     ) -> Result<(PrometheusMetrics, PrometheusMetricsRegistry), Box<dyn std::error::Error>> {
+        let extra_labels = ExtraLabels::new(self.label_extractor, self.handler_labels);
+        let label_names: Vec<&str> = self
+            .label_names
+            .names()
+            .into_iter()
+            .chain(
+                extra_labels
+                    .iter()
+                    .flat_map(|extra_labels| extra_labels.names())
+                    .map(String::as_str),
+            )
+            .collect();
+        let body_label_names: Vec<&str> = label_names.iter().copied().chain(["outcome"]).collect();
+
         let http_requests_total_opts =
             Opts::new("http_requests_total", "Total number of HTTP requests")
                 .namespace(&self.namespace)
                 .const_labels(self.const_labels.clone());
 
-        let http_requests_total =
-            IntCounterVec::new(http_requests_total_opts, &["endpoint", "method", "status"])?;
+        let http_requests_total = IntCounterVec::new(http_requests_total_opts, &label_names)?;
 
         let http_requests_duration_seconds_opts = HistogramOpts::new(
             "http_requests_duration_seconds",
@@ -89,25 +463,175 @@ impl PrometheusMetricsBuilder {
             .buckets(self.buckets.clone())
             .const_labels(self.const_labels.clone());
 
-        let http_requests_duration_seconds = HistogramVec::new(
-            http_requests_duration_seconds_opts,
-            &["endpoint", "method", "status"],
+        let http_requests_duration_seconds = match &self.summary {
+            Some(summary_opts) => DurationMetric::Summary(SummaryVec::new(
+                http_requests_duration_seconds_opts.common_opts,
+                &label_names,
+                summary_opts,
+            )?),
+            None => DurationMetric::Histogram(RouteHistogramVec::new(
+                http_requests_duration_seconds_opts,
+                &label_names,
+                &self.route_buckets,
+            )?),
+        };
+        // exemplars and native histograms complete the histogram buckets
+        let histogram_mode = matches!(http_requests_duration_seconds, DurationMetric::Histogram(_));
+
+        let http_request_size_bytes_opts = HistogramOpts::new(
+            "http_request_size_bytes",
+            "HTTP request body size in bytes for all requests",
+        )
+        .namespace(&self.namespace)
+        .buckets(self.size_buckets.clone())
+        .const_labels(self.const_labels.clone());
+
+        let http_request_size_bytes =
+            HistogramVec::new(http_request_size_bytes_opts, &label_names)?;
+
+        let http_response_size_bytes_opts = HistogramOpts::new(
+            "http_response_size_bytes",
+            "HTTP response body size in bytes for all requests",
+        )
+        .namespace(&self.namespace)
+        .buckets(self.size_buckets.clone())
+        .const_labels(self.const_labels.clone());
+
+        let http_response_size_bytes =
+            HistogramVec::new(http_response_size_bytes_opts, &label_names)?;
+
+        let http_response_body_duration_seconds_opts = HistogramOpts::new(
+            "http_response_body_duration_seconds",
+            "HTTP request duration in seconds until the response body is fully sent",
+        )
+        .namespace(&self.namespace)
+        .buckets(self.buckets.clone())
+        .const_labels(self.const_labels.clone());
+
+        let http_response_body_duration_seconds = RouteHistogramVec::new(
+            http_response_body_duration_seconds_opts,
+            &body_label_names,
+            &self.route_buckets,
         )?;
 
+        let http_requests_in_flight_opts = Opts::new(
+            "http_requests_in_flight",
+            "Number of HTTP requests currently being processed",
+        )
+        .namespace(&self.namespace)
+        .const_labels(self.const_labels.clone());
+
+        let http_requests_in_flight = IntGaugeVec::new(
+            http_requests_in_flight_opts,
+            &self.label_names.in_flight_names(),
+        )?;
+
+        let label_overflow_total_opts = Opts::new(
+            "label_overflow_total",
+            "Number of label combinations folded into the overflow series",
+        )
+        .namespace(&self.namespace)
+        .const_labels(self.const_labels.clone());
+
+        let label_overflow_total = IntCounterVec::new(label_overflow_total_opts, &["metric"])?;
+
+        let exemplars = self
+            .trace_id_extractor
+            .as_ref()
+            .filter(|_| histogram_mode)
+            .map(|_| {
+                Arc::new(Exemplars::new(
+                    http_requests_duration_seconds.desc()[0].fq_name.clone(),
+                    SeriesKeys::new(&self.const_labels, &label_names),
+                    &self.buckets,
+                    &self.route_buckets,
+                ))
+            });
+
+        #[cfg(feature = "protobuf")]
+        let native_histograms = self
+            .native_histogram_bucket_factor
+            .map(|bucket_factor| {
+                NativeHistograms::new(
+                    http_requests_duration_seconds.desc()[0].fq_name.clone(),
+                    SeriesKeys::new(&self.const_labels, &label_names),
+                    bucket_factor,
+                )
+                .map(Arc::new)
+            })
+            .transpose()?
+            .filter(|_| histogram_mode);
+
         self.registry
             .register(Box::new(http_requests_total.clone()))?;
         self.registry
             .register(Box::new(http_requests_duration_seconds.clone()))?;
+        self.registry
+            .register(Box::new(http_request_size_bytes.clone()))?;
+        self.registry
+            .register(Box::new(http_response_size_bytes.clone()))?;
+        self.registry
+            .register(Box::new(http_response_body_duration_seconds.clone()))?;
+        self.registry
+            .register(Box::new(http_requests_in_flight.clone()))?;
+        self.registry
+            .register(Box::new(label_overflow_total.clone()))?;
+
+        let series_limiter = self
+            .max_series
+            .map(|max_series| Arc::new(SeriesLimiter::new(max_series)));
 
         let prometheus_metrics = PrometheusMetrics {
             http_requests_total,
             http_requests_duration_seconds,
+            http_request_size_bytes,
+            http_response_size_bytes,
+            http_response_body_duration_seconds,
+            http_requests_in_flight,
+            label_overflow_total,
             namespace: self.namespace,
             endpoint: self.endpoint,
             const_labels: self.const_labels,
+            cancelled_status: self.cancelled_status,
+            collapse_unmatched_paths: self.collapse_unmatched_paths,
+            normalize_methods: self.normalize_methods,
+            allowed_methods: self.allowed_methods,
+            label_names: self.label_names,
+            exact_status_codes: self.exact_status_codes,
+            exclusions: Arc::new(self.exclusions),
+            series_limiter,
+            trace_id_extractor: self.trace_id_extractor.filter(|_| histogram_mode),
+            extra_labels,
+            exemplars: exemplars.clone(),
+            #[cfg(feature = "protobuf")]
+            native_histograms: native_histograms.clone(),
         };
+        // the metrics of a user registry may have been recorded before
+        let created_families = [
+            &prometheus_metrics.http_requests_total as &dyn Collector,
+            &prometheus_metrics.http_requests_duration_seconds,
+            &prometheus_metrics.http_request_size_bytes,
+            &prometheus_metrics.http_response_size_bytes,
+            &prometheus_metrics.http_response_body_duration_seconds,
+            &prometheus_metrics.label_overflow_total,
+        ]
+        .iter()
+        .flat_map(|collector| collector.desc())
+        .map(|desc| desc.fq_name.clone())
+        .collect();
         let prometheus_metrics_registry = PrometheusMetricsRegistry {
             registry: self.registry,
+            endpoint: prometheus_metrics
+                .endpoint
+                .clone()
+                .unwrap_or_else(|| DEFAULT_ENDPOINT.into()),
+            created: SystemTime::now(),
+            created_families: Arc::new(created_families),
+            compression_threshold: self.compression_threshold,
+            auth: self.auth,
+            exemplars,
+            #[cfg(feature = "protobuf")]
+            native_histograms,
         };
         Ok((prometheus_metrics, prometheus_metrics_registry))
     }
@@ -116,11 +640,31 @@ impl PrometheusMetricsBuilder {
 #[derive(Debug, Clone)]
 pub struct PrometheusMetrics {
     pub http_requests_total: IntCounterVec,
-    pub http_requests_duration_seconds: HistogramVec,
+    pub http_requests_duration_seconds: DurationMetric,
+    pub http_request_size_bytes: HistogramVec,
+    pub http_response_size_bytes: HistogramVec,
+    pub http_response_body_duration_seconds: RouteHistogramVec,
+    pub http_requests_in_flight: IntGaugeVec,
+    pub label_overflow_total: IntCounterVec,
 
     pub namespace: String,
     pub endpoint: Option<String>,
     pub const_labels: HashMap<String, String>,
+    pub cancelled_status: String,
+    pub collapse_unmatched_paths: bool,
+    pub normalize_methods: bool,
+    allowed_methods: Vec<Method>,
+    label_names: LabelNames,
+    /// status codes recorded exactly, the other ones being grouped by class,
+    /// or `None` to record every status code exactly
+    exact_status_codes: Option<Vec<StatusCode>>,
+    exclusions: Arc<Exclusions>,
+    series_limiter: Option<Arc<SeriesLimiter>>,
+    trace_id_extractor: Option<TraceIdExtractor>,
+    extra_labels: Option<ExtraLabels>,
+    exemplars: Option<Arc<Exemplars>>,
+    #[cfg(feature = "protobuf")]
+    native_histograms: Option<Arc<NativeHistograms>>,
 }
 
 impl PrometheusMetrics {
@@ -131,19 +675,173 @@ impl PrometheusMetrics {
         }
     }
 
-    fn update_metrics(&self, path: &str, method: &Method, status: StatusCode, clock: Instant) {
-        let method = method.to_string();
-        let status = status.as_u16().to_string();
+    /// Returns `true` for the requests to the metrics endpoint or excluded from the metrics
+    fn excludes(&self, path: &str, method: &Method) -> bool {
+        self.matches(path, method) || self.exclusions.excludes(path, method)
+    }
+
+    /// Replace the label values by `OVERFLOW_LABEL` if they would create
+    /// a series above the `max_series` limit
+    fn limit_series<'a>(&self, metric: &'static str, values: &[&'a str]) -> Vec<&'a str> {
+        match &self.series_limiter {
+            Some(limiter) if !limiter.admit(metric, values) => {
+                self.label_overflow_total.with_label_values(&[metric]).inc();
+                vec![OVERFLOW_LABEL; values.len()]
+            }
+            _ => values.to_vec(),
+        }
+    }
 
+    fn track_in_flight(&self, path: &str, method: &Method) -> InFlightGuard {
+        let label_values = self
+            .label_names
+            .values(path, self.method_label(method), None);
+        let labels = self.limit_series("http_requests_in_flight", &label_values);
+        let gauge = self.http_requests_in_flight.with_label_values(&labels);
+        gauge.inc();
+        InFlightGuard(gauge)
+    }
+
+    fn method_label<'a>(&self, method: &'a Method) -> &'a str {
+        if !self.normalize_methods
+            || STANDARD_METHODS.contains(method)
+            || self.allowed_methods.contains(method)
+        {
+            method.as_str()
+        } else {
+            OTHER_METHOD
+        }
+    }
+
+    fn status_label(&self, status: StatusCode) -> String {
+        match &self.exact_status_codes {
+            Some(exact) if !exact.contains(&status) => format!("{}xx", status.as_u16() / 100),
+            _ => status.as_u16().to_string(),
+        }
+    }
+
+    /// Label values of the request metrics: endpoint, method, status, then the extra labels
+    fn label_values<'a>(
+        &self,
+        path: &'a str,
+        method: &'a Method,
+        status: &'a str,
+        extra_labels: &'a [String],
+    ) -> Vec<&'a str> {
+        let mut label_values =
+            self.label_names
+                .values(path, self.method_label(method), Some(status));
+        label_values.extend(extra_labels.iter().map(String::as_str));
+        label_values
+    }
+
+    /// Route selecting the duration histogram buckets of the series
+    ///
+    /// Returns `OVERFLOW_LABEL`, selecting the default buckets, above `max_series` and
+    /// without the endpoint label: the series of a label set must stay in a single histogram
+    fn route<'a>(&self, labels: &[&'a str]) -> &'a str {
+        match self.label_names.endpoint {
+            Some(_) => labels[0],
+            None => OVERFLOW_LABEL,
+        }
+    }
+
+    /// Extra label values of a request, known before its response
+    fn request_extra_labels(&self, request: &Parts) -> HashMap<String, String> {
+        self.extra_labels
+            .as_ref()
+            .map(|extra_labels| extra_labels.request_labels(request))
+            .unwrap_or_default()
+    }
+
+    /// Extra label values in the order of the label names
+    fn extra_label_values(&self, labels: HashMap<String, String>) -> Vec<String> {
+        self.extra_labels
+            .as_ref()
+            .map(|extra_labels| extra_labels.values(labels))
+            .unwrap_or_default()
+    }
+
+    fn update_metrics(
+        &self,
+        label_values: &[&str],
+        clock: Instant,
+        request_size: u64,
+        trace_id: Option<&str>,
+    ) {
         let elapsed = clock.elapsed();
         let duration = elapsed.as_secs_f64();
+        let labels = self.limit_series("http_requests_duration_seconds", label_values);
+        let route = self.route(&labels);
         self.http_requests_duration_seconds
-            .with_label_values(&[path, &method, &status])
-            .observe(duration);
+            .observe(route, &labels, duration);
+        if let (Some(exemplars), Some(trace_id)) = (&self.exemplars, trace_id) {
+            exemplars.observe(route, &labels, duration, trace_id);
+        }
+        #[cfg(feature = "protobuf")]
+        if let Some(native_histograms) = &self.native_histograms {
+            native_histograms.observe(&labels, duration);
+        }
 
-        self.http_requests_total
-            .with_label_values(&[path, &method, &status])
-            .inc();
+        let labels = self.limit_series("http_request_size_bytes", label_values);
+        self.http_request_size_bytes
+            .with_label_values(&labels)
+            .observe(request_size as f64);
+
+        let labels = self.limit_series("http_requests_total", label_values);
+        self.http_requests_total.with_label_values(&labels).inc();
+    }
+
+    fn update_response_body_metrics(
+        &self,
+        label_values: &[&str],
+        clock: Instant,
+        response_size: u64,
+        outcome: BodyOutcome,
+    ) {
+        let labels = self.limit_series("http_response_size_bytes", label_values);
+        self.http_response_size_bytes
+            .with_label_values(&labels)
+            .observe(response_size as f64);
+
+        let mut label_values = label_values.to_vec();
+        label_values.push(outcome.as_str());
+        let labels = self.limit_series("http_response_body_duration_seconds", &label_values);
+        self.http_response_body_duration_seconds
+            .with_route(self.route(&labels))
+            .with_label_values(&labels)
+            .observe(clock.elapsed().as_secs_f64());
+    }
+}
+
+/// Tracks the label combinations already seen per metric
+#[derive(Debug)]
+struct SeriesLimiter {
+    max_series: usize,
+    series: Mutex<HashMap<&'static str, HashSet<Vec<String>>>>,
+}
+
+impl SeriesLimiter {
+    fn new(max_series: usize) -> Self {
+        Self {
+            max_series,
+            series: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns `true` if the label values are known, or can be added without exceeding the limit
+    fn admit(&self, metric: &'static str, values: &[&str]) -> bool {
+        let mut series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        let known = series.entry(metric).or_default();
+        let key: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        if known.contains(&key) {
+            true
+        } else if known.len() < self.max_series {
+            known.insert(key);
+            true
+        } else {
+            false
+        }
     }
 }
 
@@ -151,16 +849,112 @@ impl PrometheusMetrics {
 pub struct PrometheusMetricsRegistry {
     /// exposed registry for custom prometheus metrics
     pub registry: Registry,
+    endpoint: String,
+    /// exposed in the `_created` series of the OpenMetrics format
+    created: SystemTime,
+    /// names of the metrics of this crate, the only ones exposing a `_created` series
+    created_families: Arc<HashSet<String>>,
+    compression_threshold: Option<usize>,
+    auth: Option<EndpointAuth>,
+    exemplars: Option<Arc<Exemplars>>,
+    #[cfg(feature = "protobuf")]
+    native_histograms: Option<Arc<NativeHistograms>>,
 }
 
 impl PrometheusMetricsRegistry {
     #[must_use]
     pub fn metrics(&self) -> String {
+        String::from_utf8(self.encode(&TextEncoder::new()).unwrap()).unwrap()
+    }
+
+    /// Metrics in the OpenMetrics 1.0 text format
+    ///
+    /// Returns an error if the metrics cannot be encoded.
+    pub fn openmetrics(&self) -> prometheus::Result<String> {
+        let buffer = self.encode(&self.openmetrics_encoder())?;
+        String::from_utf8(buffer).map_err(|e| prometheus::Error::Msg(e.to_string()))
+    }
+
+    pub(crate) fn openmetrics_encoder(&self) -> OpenMetricsEncoder {
+        let encoder = OpenMetricsEncoder::new()
+            .created(self.created)
+            .created_families(self.created_families.clone());
+        match &self.exemplars {
+            Some(exemplars) => encoder.exemplars(exemplars.clone()),
+            None => encoder,
+        }
+    }
+
+    /// Encode the metrics of the registry with the given encoder
+    pub fn encode<E: Encoder>(&self, encoder: &E) -> prometheus::Result<Vec<u8>> {
         let mut buffer = vec![];
-        TextEncoder::new()
-            .encode(&self.registry.gather(), &mut buffer)
-            .unwrap();
-        String::from_utf8(buffer).unwrap()
+        encoder.encode(&self.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// axum handler exposing the metrics with the Prometheus `Content-Type`
+    ///
+    /// The metrics are exposed in the OpenMetrics format if requested by the `Accept` header,
+    /// (or in the protobuf format with the `protobuf` feature), in the Prometheus text format otherwise.
+    ///
+    /// Only the metric families named in the `name[]` query parameters are exposed,
+    /// like `/metrics?name[]=myapp_http_requests_total`.
+    /// Responds with a 500 status if the metrics cannot be encoded.
+    #[must_use]
+    pub fn into_handler(self) -> MetricsHandler {
+        MetricsHandler::new(self)
+    }
+
+    /// axum router exposing the metrics on the `endpoint` set in `PrometheusMetricsBuilder`
+    ///
+    /// If the `endpoint` is `None`, the metrics are exposed on `DEFAULT_ENDPOINT`.
+    pub fn router<S>(self) -> Router<S>
+    where
+        S: Clone + Send + Sync + 'static,
+    {
+        let endpoint = self.endpoint.clone();
+        Router::new().route(&endpoint, get(self.into_handler()))
+    }
+
+    /// Serve the metrics endpoint on a dedicated listener, separated from the application
+    ///
+    /// Example: "127.0.0.1:9090"
+    /// Returns an error if the address cannot be bound.
+    pub async fn serve<A: ToSocketAddrs>(self, addr: A) -> io::Result<()> {
+        self.serve_with_graceful_shutdown(addr, std::future::pending())
+            .await
+    }
+
+    /// Serve the metrics endpoint on a dedicated listener, until the `signal` future completes
+    pub async fn serve_with_graceful_shutdown<A, F>(self, addr: A, signal: F) -> io::Result<()>
+    where
+        A: ToSocketAddrs,
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let listener = TcpListener::bind(addr).await?;
+        self.serve_listener_with_graceful_shutdown(listener, signal)
+            .await
+    }
+
+    /// Serve the metrics endpoint on an already bound listener, like one bound to port 0
+    pub async fn serve_listener(self, listener: TcpListener) -> io::Result<()> {
+        self.serve_listener_with_graceful_shutdown(listener, std::future::pending())
+            .await
+    }
+
+    /// Serve the metrics endpoint on an already bound listener,
+    /// until the `signal` future completes
+    pub async fn serve_listener_with_graceful_shutdown<F>(
+        self,
+        listener: TcpListener,
+        signal: F,
+    ) -> io::Result<()>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        axum::serve(listener, self.router())
+            .with_graceful_shutdown(signal)
+            .await
     }
 }
 
@@ -170,7 +964,8 @@ impl<S> Layer<S> for PrometheusMetrics {
     fn layer(&self, inner: S) -> Self::Service {
         AxumMetrics {
             inner,
-            prometheus_metrics: self.clone(),
+            // shared by the requests, instead of cloning the labels configuration per request
+            prometheus_metrics: Arc::new(self.clone()),
         }
     }
 }
@@ -178,14 +973,15 @@ impl<S> Layer<S> for PrometheusMetrics {
 #[derive(Debug, Clone)]
 pub struct AxumMetrics<S> {
     inner: S,
-    prometheus_metrics: PrometheusMetrics,
+    prometheus_metrics: Arc<PrometheusMetrics>,
 }
 
 impl<S, R, ResBody> Service<Request<R>> for AxumMetrics<S>
     where
-        S: Service<Request<R>, Response = Response<ResBody>>,
+        S: Service<Request<RequestBody<R>>, Response = Response<ResBody>>,
+        ResBody: http_body::Body,
 {
-    type Response = Response<ResBody>;
+    type Response = Response<ResponseBody<ResBody>>;
     type Error = S::Error;
     type Future = ObservedResponseFuture<S::Future>;
 
@@ -195,48 +991,466 @@ impl<S, R, ResBody> Service<Request<R>> for AxumMetrics<S>
 
     fn call(&mut self, req: Request<R>) -> Self::Future {
         let method = req.method().clone();
-        let path = req
-            .extensions()
-            .get::<MatchedPath>() // the matched path is the route with placeholders, like "/:project_key/graphql"
-            .map_or_else(|| req.uri().path().to_string(), |p| p.as_str().to_string());
+        let observed = !self.prometheus_metrics.excludes(req.uri().path(), &method);
+        let path = match req.extensions().get::<MatchedPath>() {
+            // the matched path is the route with placeholders, like "/:project_key/graphql"
+            Some(matched_path) => matched_path.as_str().to_string(),
+            None if self.prometheus_metrics.collapse_unmatched_paths => {
+                UNMATCHED_ENDPOINT.to_string()
+            }
+            None => req.uri().path().to_string(),
+        };
+        let in_flight = observed.then(|| self.prometheus_metrics.track_in_flight(&path, &method));
+        let content_length = req
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok());
+        let request_size = RequestSize::new(content_length);
+        let (parts, body) = req.into_parts();
+        let trace_id = self
+            .prometheus_metrics
+            .trace_id_extractor
+            .as_ref()
+            .filter(|_| observed)
+            .and_then(|extractor| extractor.extract(&parts));
+        let extra_labels = if observed {
+            self.prometheus_metrics.request_extra_labels(&parts)
+        } else {
+            HashMap::new()
+        };
+        let req = Request::from_parts(parts, RequestBody::new(body, request_size.counter()));
         ObservedResponseFuture {
             inner: self.inner.call(req),
             time: Instant::now(),
             method,
             path,
-            prometheus_metrics: Arc::new(self.prometheus_metrics.clone()),
+            request_size,
+            trace_id,
+            extra_labels,
+            prometheus_metrics: self.prometheus_metrics.clone(),
+            observed,
+            completed: false,
+            in_flight,
         }
     }
 }
 
-#[pin_project]
+#[pin_project(PinnedDrop)]
 pub struct ObservedResponseFuture<F> {
     #[pin]
     inner: F,
     time: Instant,
     method: Method,
     path: String,
+    request_size: RequestSize,
+    trace_id: Option<String>,
+    /// extra labels known from the request, completed with the ones of the response
+    extra_labels: HashMap<String, String>,
     prometheus_metrics: Arc<PrometheusMetrics>,
+    /// false for requests to the metrics endpoint and excluded requests
+    observed: bool,
+    completed: bool,
+    /// decrements the in-flight gauge when the future completes or is dropped
+    in_flight: Option<InFlightGuard>,
+}
+
+struct InFlightGuard(IntGauge);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
 }
 
 impl<F, B, E> Future for ObservedResponseFuture<F>
     where
         F: Future<Output = Result<Response<B>, E>>,
+        B: http_body::Body,
 {
-    type Output = Result<Response<B>, E>;
+    type Output = Result<Response<ResponseBody<B>>, E>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.project();
-        let response: Response<B> = futures_core::ready!(this.inner.poll(cx))?;
+        let result: Result<Response<B>, E> = futures_core::ready!(this.inner.poll(cx));
+        *this.completed = true;
+        this.in_flight.take();
 
         let prometheus_metrics = this.prometheus_metrics;
         let path = &this.path;
         let method = &this.method;
 
-        if !prometheus_metrics.matches(path, method) {
-            prometheus_metrics.update_metrics(path, method, response.status(), *this.time);
+        if !*this.observed {
+            return Poll::Ready(
+                result.map(|response| response.map(|body| ResponseBody::new(body, None))),
+            );
+        }
+
+        let mut extra_labels = std::mem::take(this.extra_labels);
+        let result = result.map(|response| match &prometheus_metrics.extra_labels {
+            Some(labels) => {
+                let (parts, body) = response.into_parts();
+                labels.response_labels(&parts, &mut extra_labels);
+                Response::from_parts(parts, body)
+            }
+            None => response,
+        });
+        let extra_labels = prometheus_metrics.extra_label_values(extra_labels);
+
+        let status = match &result {
+            Ok(response) => prometheus_metrics.status_label(response.status()),
+            Err(_) => ERROR_STATUS.to_string(),
+        };
+        let label_values = prometheus_metrics.label_values(path, method, &status, &extra_labels);
+        prometheus_metrics.update_metrics(
+            &label_values,
+            *this.time,
+            this.request_size.get(),
+            this.trace_id.as_deref(),
+        );
+        let labels = label_values.iter().map(|value| value.to_string()).collect();
+
+        Poll::Ready(result.map(|response| {
+            let status = response.status();
+            // hyper never polls the body of these responses, which have no content
+            let bodyless = **method == Method::HEAD
+                || status.is_informational()
+                || status == StatusCode::NO_CONTENT
+                || status == StatusCode::NOT_MODIFIED;
+            let observer = ResponseBodyObserver {
+                prometheus_metrics: prometheus_metrics.clone(),
+                labels,
+                time: *this.time,
+                bytes: 0,
+                outcome: if bodyless {
+                    BodyOutcome::Completed
+                } else {
+                    BodyOutcome::Dropped
+                },
+            };
+            response.map(|body| ResponseBody::new(body, Some(observer)))
+        }))
+    }
+}
+
+#[pinned_drop]
+impl<F> PinnedDrop for ObservedResponseFuture<F> {
+    fn drop(self: Pin<&mut Self>) {
+        let this = self.project();
+        if *this.completed {
+            return;
+        }
+
+        let prometheus_metrics = this.prometheus_metrics;
+        let path = &this.path;
+        let method = &this.method;
+
+        if *this.observed {
+            let extra_labels =
+                prometheus_metrics.extra_label_values(std::mem::take(this.extra_labels));
+            let label_values = prometheus_metrics.label_values(
+                path,
+                method,
+                &prometheus_metrics.cancelled_status,
+                &extra_labels,
+            );
+            prometheus_metrics.update_metrics(
+                &label_values,
+                *this.time,
+                this.request_size.get(),
+                this.trace_id.as_deref(),
+            );
+        }
+    }
+}
+
+/// Records the response body metrics when dropped
+#[derive(Debug)]
+pub(crate) struct ResponseBodyObserver {
+    prometheus_metrics: Arc<PrometheusMetrics>,
+    /// label values of the request metrics
+    labels: Vec<String>,
+    time: Instant,
+    bytes: u64,
+    /// how the response body ended, `Dropped` until it reaches its end
+    pub(crate) outcome: BodyOutcome,
+}
+
+impl ResponseBodyObserver {
+    pub(crate) fn on_data(&mut self, bytes: usize) {
+        self.bytes += bytes as u64;
+    }
+}
+
+impl Drop for ResponseBodyObserver {
+    fn drop(&mut self) {
+        let labels: Vec<&str> = self.labels.iter().map(String::as_str).collect();
+        self.prometheus_metrics.update_response_body_metrics(
+            &labels,
+            self.time,
+            self.bytes,
+            self.outcome,
+        );
+    }
+}
+
+/// How a response body ended, recorded in the `outcome` label
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum BodyOutcome {
+    /// the body reached its end
+    Completed,
+    /// the body returned an error
+    Error,
+    /// the body was dropped before reaching its end (client disconnect...)
+    Dropped,
+}
+
+impl BodyOutcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            BodyOutcome::Completed => "completed",
+            BodyOutcome::Error => "error",
+            BodyOutcome::Dropped => "dropped",
         }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prometheus_metrics(builder: PrometheusMetricsBuilder) -> PrometheusMetrics {
+        builder.pair().unwrap().0
+    }
+
+    #[test]
+    fn admits_series_up_to_the_limit() {
+        let limiter = SeriesLimiter::new(2);
+        assert!(limiter.admit("metric", &["/a", "GET"]));
+        assert!(limiter.admit("metric", &["/b", "GET"]));
+        assert!(!limiter.admit("metric", &["/c", "GET"]));
+        // known series are still admitted
+        assert!(limiter.admit("metric", &["/a", "GET"]));
+        assert!(limiter.admit("metric", &["/b", "GET"]));
+        // the limit is per metric
+        assert!(limiter.admit("other", &["/c", "GET"]));
+    }
+
+    #[test]
+    fn folds_new_series_into_the_overflow_series() {
+        let prometheus_metrics =
+            prometheus_metrics(PrometheusMetricsBuilder::new("test").max_series(1));
+        let overflow = |metric| {
+            prometheus_metrics
+                .label_overflow_total
+                .with_label_values(&[metric])
+                .get()
+        };
+
+        let a = ["/a", "GET", "200"];
+        let b = ["/b", "GET", "200"];
+        assert_eq!(
+            prometheus_metrics.limit_series("http_requests_total", &a),
+            a
+        );
+        assert_eq!(
+            prometheus_metrics.limit_series("http_requests_total", &b),
+            [OVERFLOW_LABEL; 3]
+        );
+        assert_eq!(
+            prometheus_metrics.limit_series("http_requests_total", &a),
+            a
+        );
+        assert_eq!(overflow("http_requests_total"), 1);
+
+        assert_eq!(
+            prometheus_metrics.limit_series("http_request_size_bytes", &b),
+            b
+        );
+        assert_eq!(
+            prometheus_metrics.limit_series("http_request_size_bytes", &a),
+            [OVERFLOW_LABEL; 3]
+        );
+        assert_eq!(overflow("http_request_size_bytes"), 1);
+        assert_eq!(overflow("http_requests_total"), 1);
+    }
+
+    #[test]
+    fn tracks_in_flight_requests_of_the_overflow_series() {
+        let prometheus_metrics =
+            prometheus_metrics(PrometheusMetricsBuilder::new("test").max_series(1));
+        let in_flight = |labels: &[&str]| {
+            prometheus_metrics
+                .http_requests_in_flight
+                .with_label_values(labels)
+                .get()
+        };
+
+        let a = prometheus_metrics.track_in_flight("/a", &Method::GET);
+        let b = prometheus_metrics.track_in_flight("/b", &Method::GET);
+        let c = prometheus_metrics.track_in_flight("/c", &Method::GET);
+        assert_eq!(in_flight(&["/a", "GET"]), 1);
+        assert_eq!(in_flight(&[OVERFLOW_LABEL, OVERFLOW_LABEL]), 2);
+        assert_eq!(
+            prometheus_metrics
+                .label_overflow_total
+                .with_label_values(&["http_requests_in_flight"])
+                .get(),
+            2
+        );
+
+        drop(b);
+        assert_eq!(in_flight(&[OVERFLOW_LABEL, OVERFLOW_LABEL]), 1);
+        drop(c);
+        drop(a);
+        assert_eq!(in_flight(&["/a", "GET"]), 0);
+        assert_eq!(in_flight(&[OVERFLOW_LABEL, OVERFLOW_LABEL]), 0);
+    }
+
+    #[test]
+    fn excludes_the_metrics_endpoint_and_the_excluded_requests() {
+        let prometheus_metrics = prometheus_metrics(
+            PrometheusMetricsBuilder::new("test")
+                .exclude_paths([PathMatcher::exact("/health")])
+                .exclude_methods(&[Method::OPTIONS]),
+        );
+        assert!(prometheus_metrics.excludes("/metrics", &Method::GET));
+        assert!(!prometheus_metrics.excludes("/metrics", &Method::POST));
+        assert!(prometheus_metrics.excludes("/health", &Method::GET));
+        assert!(prometheus_metrics.excludes("/users", &Method::OPTIONS));
+        assert!(!prometheus_metrics.excludes("/users", &Method::GET));
+    }
+
+    #[test]
+    fn records_the_renamed_labels() {
+        let prometheus_metrics = prometheus_metrics(
+            PrometheusMetricsBuilder::new("test")
+                .endpoint_label(Some("route"))
+                .status_label(None),
+        );
+        let label_names = |collector: &dyn Collector| collector.desc()[0].variable_labels.clone();
+        assert_eq!(
+            label_names(&prometheus_metrics.http_requests_total),
+            ["route", "method"]
+        );
+        assert_eq!(
+            label_names(&prometheus_metrics.http_response_body_duration_seconds),
+            ["route", "method", "outcome"]
+        );
+        assert_eq!(
+            label_names(&prometheus_metrics.http_requests_in_flight),
+            ["route", "method"]
+        );
+        assert_eq!(
+            prometheus_metrics.label_values("/", &Method::GET, "200", &[]),
+            ["/", "GET"]
+        );
+    }
+
+    #[test]
+    fn records_exact_status_codes_by_default() {
+        let prometheus_metrics = prometheus_metrics(PrometheusMetricsBuilder::new("test"));
+        assert_eq!(prometheus_metrics.status_label(StatusCode::OK), "200");
+        assert_eq!(
+            prometheus_metrics.status_label(StatusCode::TOO_MANY_REQUESTS),
+            "429"
+        );
+    }
+
+    #[test]
+    fn groups_status_codes_by_class() {
+        let prometheus_metrics =
+            prometheus_metrics(PrometheusMetricsBuilder::new("test").group_status_codes(&[]));
+        assert_eq!(prometheus_metrics.status_label(StatusCode::OK), "2xx");
+        assert_eq!(
+            prometheus_metrics.status_label(StatusCode::SWITCHING_PROTOCOLS),
+            "1xx"
+        );
+        assert_eq!(
+            prometheus_metrics.status_label(StatusCode::TOO_MANY_REQUESTS),
+            "4xx"
+        );
+        assert_eq!(
+            prometheus_metrics.status_label(StatusCode::SERVICE_UNAVAILABLE),
+            "5xx"
+        );
+    }
+
+    #[test]
+    fn keeps_the_allowed_status_codes_exact() {
+        let prometheus_metrics =
+            prometheus_metrics(PrometheusMetricsBuilder::new("test").group_status_codes(&[
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::SERVICE_UNAVAILABLE,
+            ]));
+        assert_eq!(
+            prometheus_metrics.status_label(StatusCode::TOO_MANY_REQUESTS),
+            "429"
+        );
+        assert_eq!(
+            prometheus_metrics.status_label(StatusCode::SERVICE_UNAVAILABLE),
+            "503"
+        );
+        assert_eq!(
+            prometheus_metrics.status_label(StatusCode::NOT_FOUND),
+            "4xx"
+        );
+        assert_eq!(
+            prometheus_metrics.status_label(StatusCode::INTERNAL_SERVER_ERROR),
+            "5xx"
+        );
+    }
+
+    #[test]
+    fn normalizes_non_standard_methods() {
+        let prometheus_metrics = prometheus_metrics(PrometheusMetricsBuilder::new("test"));
+        let propfind = Method::from_bytes(b"PROPFIND").unwrap();
+        assert_eq!(prometheus_metrics.method_label(&Method::GET), "GET");
+        assert_eq!(prometheus_metrics.method_label(&Method::PATCH), "PATCH");
+        assert_eq!(prometheus_metrics.method_label(&propfind), OTHER_METHOD);
+    }
+
+    #[test]
+    fn keeps_the_allowed_methods() {
+        let prometheus_metrics = prometheus_metrics(
+            PrometheusMetricsBuilder::new("test")
+                .allow_methods(&[Method::from_bytes(b"PROPFIND").unwrap()]),
+        );
+        let propfind = Method::from_bytes(b"PROPFIND").unwrap();
+        let purge = Method::from_bytes(b"PURGE").unwrap();
+        assert_eq!(prometheus_metrics.method_label(&propfind), "PROPFIND");
+        assert_eq!(prometheus_metrics.method_label(&purge), OTHER_METHOD);
+    }
+
+    #[test]
+    fn keeps_all_methods_without_normalization() {
+        let prometheus_metrics =
+            prometheus_metrics(PrometheusMetricsBuilder::new("test").normalize_methods(false));
+        let propfind = Method::from_bytes(b"PROPFIND").unwrap();
+        assert_eq!(prometheus_metrics.method_label(&propfind), "PROPFIND");
+        assert_eq!(prometheus_metrics.method_label(&Method::GET), "GET");
+    }
+
+    #[test]
+    fn exposes_the_created_series_of_the_own_metrics_only() {
+        let registry = Registry::new();
+        let jobs = IntCounterVec::new(Opts::new("jobs_total", "Jobs"), &["queue"]).unwrap();
+        registry.register(Box::new(jobs.clone())).unwrap();
+        jobs.with_label_values(&["default"]).inc();
+
+        let (prometheus_metrics, prometheus_metrics_registry) =
+            PrometheusMetricsBuilder::new("test")
+                .registry(registry)
+                .pair()
+                .unwrap();
+        prometheus_metrics
+            .http_requests_total
+            .with_label_values(&["/", "GET", "200"])
+            .inc();
 
-        Poll::Ready(Ok(response))
+        let openmetrics = prometheus_metrics_registry.openmetrics().unwrap();
+        assert!(openmetrics.contains("\njobs_total{queue=\"default\"} 1\n"));
+        assert!(!openmetrics.contains("jobs_created"));
+        assert!(openmetrics.contains("\ntest_http_requests_created{"));
     }
 }
@@ -0,0 +1,333 @@
+use crate::series::{SeriesKeys, SeriesLabels};
+use prometheus::proto::{Histogram, MetricFamily};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, PoisonError};
+
+/// Same default as the Go client: observations below are counted in the zero bucket
+const ZERO_THRESHOLD: f64 = 2.938735877055719e-39;
+
+// field numbers of the native histogram in `io.prometheus.client.Histogram`
+const SCHEMA: u32 = 5;
+const ZERO_THRESHOLD_FIELD: u32 = 6;
+const ZERO_COUNT: u32 = 7;
+const POSITIVE_SPAN: u32 = 12;
+const POSITIVE_DELTA: u32 = 13;
+
+/// Sparse exponential buckets of the series of a histogram, exposed as
+/// Prometheus native histograms in the protobuf format
+#[derive(Debug)]
+pub(crate) struct NativeHistograms {
+    /// fully qualified name of the classic histogram carrying the native buckets
+    name: String,
+    keys: SeriesKeys,
+    schema: i32,
+    series: Mutex<HashMap<SeriesLabels, NativeHistogram>>,
+}
+
+/// The count and sum are kept with the buckets, so that a scrape sees them consistent
+#[derive(Debug, Default)]
+struct NativeHistogram {
+    count: u64,
+    sum: f64,
+    zero_count: u64,
+    /// bucket index -> count
+    positive: BTreeMap<i32, u64>,
+}
+
+impl NativeHistograms {
+    /// Returns an error if `bucket_factor` is not a finite number above 1
+    pub(crate) fn new(
+        name: String,
+        keys: SeriesKeys,
+        bucket_factor: f64,
+    ) -> prometheus::Result<Self> {
+        if !bucket_factor.is_finite() || bucket_factor <= 1.0 {
+            return Err(prometheus::Error::Msg(format!(
+                "native histogram bucket factor {bucket_factor} must be a finite number above 1"
+            )));
+        }
+        Ok(Self {
+            name,
+            keys,
+            schema: schema(bucket_factor),
+            series: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub(crate) fn observe(&self, label_values: &[&str], value: f64) {
+        let labels = self.keys.key(label_values);
+        let mut series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        let histogram = series.entry(labels).or_default();
+        histogram.count += 1;
+        histogram.sum += value;
+        if value.abs() <= ZERO_THRESHOLD || value.is_sign_negative() {
+            // request durations are never negative
+            histogram.zero_count += 1;
+        } else {
+            *histogram
+                .positive
+                .entry(bucket_index(value, self.schema))
+                .or_default() += 1;
+        }
+    }
+
+    /// Add the native buckets to the gathered classic histograms
+    ///
+    /// The native histogram fields are unknown to the `prometheus` crate proto model,
+    /// so they are added as unknown fields, serialized by the `ProtobufEncoder`.
+    /// The sample count and sum of the classic histograms, recorded separately,
+    /// are replaced by the native ones to match the native buckets.
+    pub(crate) fn attach(&self, metric_families: &mut [MetricFamily]) {
+        let series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        for mf in metric_families
+            .iter_mut()
+            .filter(|mf| mf.get_name() == self.name)
+        {
+            for m in mf.mut_metric().iter_mut() {
+                let labels = SeriesKeys::metric_key(m.get_label());
+                if let Some(native) = series.get(&labels) {
+                    native.encode(self.schema, m.mut_histogram());
+                }
+            }
+        }
+    }
+}
+
+impl NativeHistogram {
+    fn encode(&self, schema: i32, histogram: &mut Histogram) {
+        histogram.set_sample_count(self.count);
+        histogram.set_sample_sum(self.sum);
+        let fields = &mut histogram.unknown_fields;
+        fields.add_varint(SCHEMA, zigzag(schema.into()));
+        fields.add_fixed64(ZERO_THRESHOLD_FIELD, ZERO_THRESHOLD.to_bits());
+        fields.add_varint(ZERO_COUNT, self.zero_count);
+
+        if self.positive.is_empty() {
+            // an empty span marks the histogram as native even without observation
+            fields.add_length_delimited(POSITIVE_SPAN, bucket_span(0, 0));
+            return;
+        }
+
+        // consecutive buckets are grouped in spans, each bucket count is a delta to the previous one
+        let mut span: Option<(i32, u32)> = None;
+        let mut previous_index = 0;
+        let mut previous_count = 0;
+        for (&index, &count) in &self.positive {
+            span = match span {
+                Some((offset, length)) if index == previous_index + 1 => Some((offset, length + 1)),
+                Some((offset, length)) => {
+                    fields.add_length_delimited(POSITIVE_SPAN, bucket_span(offset, length));
+                    Some((index - previous_index - 1, 1))
+                }
+                None => Some((index, 1)),
+            };
+            fields.add_varint(POSITIVE_DELTA, zigzag(count as i64 - previous_count as i64));
+            previous_index = index;
+            previous_count = count;
+        }
+        if let Some((offset, length)) = span {
+            fields.add_length_delimited(POSITIVE_SPAN, bucket_span(offset, length));
+        }
+    }
+}
+
+/// Highest resolution schema whose bucket growth factor is not above `bucket_factor`,
+/// like the Go client
+fn schema(bucket_factor: f64) -> i32 {
+    let floor = bucket_factor.log2().log2().floor();
+    if floor <= -8.0 {
+        8
+    } else if floor >= 4.0 {
+        -4
+    } else {
+        -(floor as i32)
+    }
+}
+
+/// Index of the bucket `(base^(index-1), base^index]` containing `value`,
+/// with `base = 2^(2^-schema)`
+fn bucket_index(value: f64, schema: i32) -> i32 {
+    if schema > 0 {
+        let scale = f64::from(1 << schema);
+        let mut index = (value.log2() * scale).ceil() as i32;
+        // correct the floating point rounding around the bucket bounds
+        if value > (f64::from(index) / scale).exp2() {
+            index += 1;
+        } else if value <= (f64::from(index - 1) / scale).exp2() {
+            index -= 1;
+        }
+        index
+    } else {
+        // value = frac * 2^exp, with frac in [0.5, 1)
+        let exp = value.log2().floor() as i32 + 1;
+        let frac = value / f64::from(exp).exp2();
+        let index = if frac == 0.5 { exp - 1 } else { exp };
+        let offset = (1 << -schema) - 1;
+        (index + offset) >> -schema
+    }
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+/// Serialized `io.prometheus.client.BucketSpan` message
+fn bucket_span(offset: i32, length: u32) -> Vec<u8> {
+    let mut buffer = vec![];
+    // field 1: sint32 offset
+    buffer.push(1 << 3);
+    write_varint(&mut buffer, zigzag(offset.into()));
+    // field 2: uint32 length
+    buffer.push(2 << 3);
+    write_varint(&mut buffer, length.into());
+    buffer
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use prometheus::{HistogramOpts, HistogramVec, Registry};
+
+    #[test]
+    fn selects_the_schema_of_the_bucket_factor() {
+        assert_eq!(schema(1.1), 3);
+        assert_eq!(schema(2.0), 0);
+        assert_eq!(schema(4.0), -1);
+        // clamped to the supported schemas
+        assert_eq!(schema(1.0001), 8);
+        assert_eq!(schema(1e6), -4);
+    }
+
+    #[test]
+    fn rejects_invalid_bucket_factors() {
+        for bucket_factor in [1.0, 0.5, 0.0, -2.0, f64::NAN, f64::INFINITY] {
+            let native = NativeHistograms::new(
+                "duration".into(),
+                SeriesKeys::new(&HashMap::new(), &[]),
+                bucket_factor,
+            );
+            assert!(native.is_err(), "{bucket_factor}");
+        }
+        assert!(crate::PrometheusMetricsBuilder::new("test")
+            .native_histograms(1.0)
+            .pair()
+            .is_err());
+        assert!(crate::PrometheusMetricsBuilder::new("test")
+            .native_histograms(1.1)
+            .pair()
+            .is_ok());
+    }
+
+    #[test]
+    fn indexes_buckets_with_upper_inclusive_bounds() {
+        assert_eq!(bucket_index(1.0, 0), 0);
+        assert_eq!(bucket_index(2.0, 0), 1);
+        assert_eq!(bucket_index(3.0, 0), 2);
+        assert_eq!(bucket_index(4.0, 0), 2);
+        assert_eq!(bucket_index(0.5, 0), -1);
+
+        assert_eq!(bucket_index(1.0, 3), 0);
+        assert_eq!(bucket_index(2f64.powf(1.0 / 8.0), 3), 1);
+        assert_eq!(bucket_index(2.0, 3), 8);
+        assert_eq!(bucket_index(2.0 + f64::EPSILON * 2.0, 3), 9);
+        assert_eq!(bucket_index(0.5, 3), -8);
+
+        // base 4
+        assert_eq!(bucket_index(1.0, -1), 0);
+        assert_eq!(bucket_index(4.0, -1), 1);
+        assert_eq!(bucket_index(5.0, -1), 2);
+        assert_eq!(bucket_index(16.0, -1), 2);
+        assert_eq!(bucket_index(0.25, -1), -1);
+    }
+
+    #[test]
+    fn serializes_bucket_spans() {
+        assert_eq!(bucket_span(0, 0), [0x08, 0x00, 0x10, 0x00]);
+        assert_eq!(bucket_span(-1, 3), [0x08, 0x01, 0x10, 0x03]);
+        assert_eq!(bucket_span(2, 300), [0x08, 0x04, 0x10, 0xAC, 0x02]);
+    }
+
+    #[test]
+    fn encodes_spans_and_deltas() {
+        let native = NativeHistogram {
+            count: 7,
+            sum: 12.5,
+            zero_count: 1,
+            positive: BTreeMap::from([(1, 2), (2, 3), (5, 1)]),
+        };
+        let mut histogram = Histogram::default();
+        native.encode(3, &mut histogram);
+
+        assert_eq!(histogram.get_sample_count(), 7);
+        assert_eq!(histogram.get_sample_sum(), 12.5);
+        let fields = &histogram.unknown_fields;
+        assert_eq!(fields.get(SCHEMA).unwrap().varint, [6]);
+        assert_eq!(
+            fields.get(ZERO_THRESHOLD_FIELD).unwrap().fixed64,
+            [ZERO_THRESHOLD.to_bits()]
+        );
+        assert_eq!(fields.get(ZERO_COUNT).unwrap().varint, [1]);
+        // buckets 1 and 2, then bucket 5 after a gap of 2
+        assert_eq!(
+            fields.get(POSITIVE_SPAN).unwrap().length_delimited,
+            [bucket_span(1, 2), bucket_span(2, 1)]
+        );
+        // deltas 2, 1 and -2
+        assert_eq!(fields.get(POSITIVE_DELTA).unwrap().varint, [4, 2, 3]);
+    }
+
+    #[test]
+    fn encodes_an_empty_span_without_observation() {
+        let mut histogram = Histogram::default();
+        NativeHistogram::default().encode(0, &mut histogram);
+
+        let fields = &histogram.unknown_fields;
+        assert_eq!(
+            fields.get(POSITIVE_SPAN).unwrap().length_delimited,
+            [bucket_span(0, 0)]
+        );
+        assert!(fields.get(POSITIVE_DELTA).is_none());
+    }
+
+    #[test]
+    fn replaces_the_classic_count_and_sum() {
+        let registry = Registry::new();
+        let histogram =
+            HistogramVec::new(HistogramOpts::new("duration", "help"), &["endpoint"]).unwrap();
+        registry.register(Box::new(histogram.clone())).unwrap();
+        let native = NativeHistograms::new(
+            "duration".into(),
+            SeriesKeys::new(&HashMap::new(), &["endpoint"]),
+            2.0,
+        )
+        .unwrap();
+
+        // a classic observation not yet recorded in the native histogram
+        histogram.with_label_values(&["/"]).observe(1.0);
+        native.observe(&["/"], 0.0);
+        native.observe(&["/"], 1.0);
+        native.observe(&["/"], 3.0);
+
+        let mut metric_families = registry.gather();
+        native.attach(&mut metric_families);
+        let histogram = metric_families[0].get_metric()[0].get_histogram();
+        assert_eq!(histogram.get_sample_count(), 3);
+        assert_eq!(histogram.get_sample_sum(), 4.0);
+        assert_eq!(
+            histogram.unknown_fields.get(ZERO_COUNT).unwrap().varint,
+            [1]
+        );
+        assert_eq!(
+            histogram.unknown_fields.get(POSITIVE_DELTA).unwrap().varint,
+            [2, 0]
+        );
+    }
+}
@@ -0,0 +1,408 @@
+use crate::exemplars::{Exemplar, Exemplars};
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType};
+use prometheus::Encoder;
+use std::collections::HashSet;
+use std::io::Write;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The OpenMetrics 1.0 text format
+pub const OPENMETRICS_FORMAT: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Units inferred from the metric name suffix, and exposed as `# UNIT` metadata
+const UNITS: &[&str] = &["seconds", "bytes"];
+
+/// An `Encoder` converting metric families into the OpenMetrics 1.0 text format
+///
+/// Counters, histograms and summaries are exposed with a `_created` series
+/// when a creation time is set. The request duration histogram exposes
+/// its exemplars when enabled in `PrometheusMetricsBuilder`.
+#[derive(Debug, Default, Clone)]
+pub struct OpenMetricsEncoder {
+    created: Option<SystemTime>,
+    /// names of the families exposing the `_created` series, all of them if `None`
+    created_families: Option<Arc<HashSet<String>>>,
+    exemplars: Option<Arc<Exemplars>>,
+}
+
+impl OpenMetricsEncoder {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the time since when the metrics are recorded, exposed in the `_created` series
+    ///
+    /// The time applies to every counter, histogram and summary encoded:
+    /// only set it if they all started from zero at that time.
+    #[must_use]
+    pub fn created(mut self, value: SystemTime) -> Self {
+        self.created = Some(value);
+        self
+    }
+
+    /// Only expose the `_created` series of the families with these names
+    #[must_use]
+    pub(crate) fn created_families(mut self, value: Arc<HashSet<String>>) -> Self {
+        self.created_families = Some(value);
+        self
+    }
+
+    /// Attach the exemplars to the buckets of their histogram
+    #[must_use]
+    pub(crate) fn exemplars(mut self, value: Arc<Exemplars>) -> Self {
+        self.exemplars = Some(value);
+        self
+    }
+
+    fn created_seconds(&self) -> Option<f64> {
+        self.created
+            .and_then(|created| created.duration_since(UNIX_EPOCH).ok())
+            .map(|created| created.as_secs_f64())
+    }
+}
+
+impl Encoder for OpenMetricsEncoder {
+    fn encode<W: Write>(
+        &self,
+        metric_families: &[MetricFamily],
+        writer: &mut W,
+    ) -> prometheus::Result<()> {
+        for mf in metric_families {
+            let created = self.created_seconds().filter(|_| {
+                self.created_families
+                    .as_ref()
+                    .is_none_or(|families| families.contains(mf.get_name()))
+            });
+            let metric_type = mf.get_field_type();
+            // counter samples are suffixed by `_total`, but the family name is not
+            let name = match metric_type {
+                MetricType::COUNTER => mf
+                    .get_name()
+                    .strip_suffix("_total")
+                    .unwrap_or(mf.get_name()),
+                _ => mf.get_name(),
+            };
+
+            let type_name = match metric_type {
+                MetricType::COUNTER => "counter",
+                MetricType::GAUGE => "gauge",
+                MetricType::HISTOGRAM => "histogram",
+                MetricType::SUMMARY => "summary",
+                MetricType::UNTYPED => "unknown",
+            };
+            writeln!(writer, "# TYPE {name} {type_name}")?;
+            if let Some(unit) = UNITS.iter().find(|unit| {
+                name.strip_suffix(*unit)
+                    .is_some_and(|prefix| prefix.ends_with('_'))
+            }) {
+                writeln!(writer, "# UNIT {name} {unit}")?;
+            }
+            if !mf.get_help().is_empty() {
+                writeln!(writer, "# HELP {name} {}", escape(mf.get_help()))?;
+            }
+
+            for m in mf.get_metric() {
+                match metric_type {
+                    MetricType::COUNTER => {
+                        write_sample(
+                            writer,
+                            name,
+                            "_total",
+                            m,
+                            None,
+                            m.get_counter().get_value(),
+                            None,
+                        )?;
+                        if let Some(created) = created {
+                            write_sample(writer, name, "_created", m, None, created, None)?;
+                        }
+                    }
+                    MetricType::GAUGE => {
+                        write_sample(writer, name, "", m, None, m.get_gauge().get_value(), None)?;
+                    }
+                    MetricType::HISTOGRAM => {
+                        let h = m.get_histogram();
+                        let exemplars = self
+                            .exemplars
+                            .as_ref()
+                            .filter(|exemplars| exemplars.name() == name)
+                            .and_then(|exemplars| exemplars.get(m.get_label()))
+                            .unwrap_or_default();
+                        let exemplar = |i: usize| exemplars.get(i).and_then(Option::as_ref);
+                        let mut inf_seen = false;
+                        for (i, b) in h.get_bucket().iter().enumerate() {
+                            let upper_bound = b.get_upper_bound();
+                            inf_seen |= upper_bound == f64::INFINITY;
+                            write_sample(
+                                writer,
+                                name,
+                                "_bucket",
+                                m,
+                                Some(("le", &format_bound(upper_bound))),
+                                b.get_cumulative_count() as f64,
+                                exemplar(i),
+                            )?;
+                        }
+                        if !inf_seen {
+                            write_sample(
+                                writer,
+                                name,
+                                "_bucket",
+                                m,
+                                Some(("le", "+Inf")),
+                                h.get_sample_count() as f64,
+                                exemplar(h.get_bucket().len()),
+                            )?;
+                        }
+                        write_sample(
+                            writer,
+                            name,
+                            "_count",
+                            m,
+                            None,
+                            h.get_sample_count() as f64,
+                            None,
+                        )?;
+                        write_sample(writer, name, "_sum", m, None, h.get_sample_sum(), None)?;
+                        if let Some(created) = created {
+                            write_sample(writer, name, "_created", m, None, created, None)?;
+                        }
+                    }
+                    MetricType::SUMMARY => {
+                        let s = m.get_summary();
+                        for q in s.get_quantile() {
+                            write_sample(
+                                writer,
+                                name,
+                                "",
+                                m,
+                                Some(("quantile", &format_bound(q.get_quantile()))),
+                                q.get_value(),
+                                None,
+                            )?;
+                        }
+                        write_sample(
+                            writer,
+                            name,
+                            "_count",
+                            m,
+                            None,
+                            s.get_sample_count() as f64,
+                            None,
+                        )?;
+                        write_sample(writer, name, "_sum", m, None, s.get_sample_sum(), None)?;
+                        if let Some(created) = created {
+                            write_sample(writer, name, "_created", m, None, created, None)?;
+                        }
+                    }
+                    MetricType::UNTYPED => {
+                        #[allow(deprecated)]
+                        write_sample(writer, name, "", m, None, m.get_untyped().get_value(), None)?;
+                    }
+                }
+            }
+        }
+
+        writer.write_all(b"# EOF\n")?;
+        Ok(())
+    }
+
+    fn format_type(&self) -> &str {
+        OPENMETRICS_FORMAT
+    }
+}
+
+fn write_sample<W: Write>(
+    writer: &mut W,
+    name: &str,
+    suffix: &str,
+    m: &Metric,
+    additional_label: Option<(&str, &str)>,
+    value: f64,
+    exemplar: Option<&Exemplar>,
+) -> prometheus::Result<()> {
+    write!(writer, "{name}{suffix}")?;
+    write_labels(writer, m.get_label(), additional_label)?;
+    write!(writer, " {}", format_value(value))?;
+    let timestamp = m.get_timestamp_ms();
+    if timestamp != 0 {
+        // OpenMetrics timestamps are in seconds
+        write!(writer, " {}", timestamp as f64 / 1000.0)?;
+    }
+    if let Some(exemplar) = exemplar {
+        write!(
+            writer,
+            " # {{trace_id=\"{}\"}} {} {}",
+            escape(&exemplar.trace_id),
+            format_value(exemplar.value),
+            exemplar.timestamp
+        )?;
+    }
+    writer.write_all(b"\n")?;
+    Ok(())
+}
+
+fn write_labels<W: Write>(
+    writer: &mut W,
+    labels: &[LabelPair],
+    additional_label: Option<(&str, &str)>,
+) -> prometheus::Result<()> {
+    if labels.is_empty() && additional_label.is_none() {
+        return Ok(());
+    }
+    let labels = labels
+        .iter()
+        .map(|label| (label.get_name(), label.get_value()))
+        .chain(additional_label);
+    writer.write_all(b"{")?;
+    for (i, (name, value)) in labels.enumerate() {
+        if i > 0 {
+            writer.write_all(b",")?;
+        }
+        write!(writer, "{name}=\"{}\"", escape(value))?;
+    }
+    writer.write_all(b"}")?;
+    Ok(())
+}
+
+fn format_value(value: f64) -> String {
+    if value.is_nan() {
+        "NaN".to_string()
+    } else if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Bucket bounds and quantiles are always written as floats, like "1.0"
+fn format_bound(value: f64) -> String {
+    if value.is_finite() {
+        format!("{value:?}")
+    } else {
+        format_value(value)
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('\n', r"\n")
+        .replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::series::SeriesKeys;
+    use crate::summary::{SummaryOpts, SummaryVec};
+    use prometheus::core::Collector;
+    use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts, Registry};
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    fn encode(encoder: &OpenMetricsEncoder, metric_families: &[MetricFamily]) -> String {
+        let mut buffer = vec![];
+        encoder.encode(metric_families, &mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn encodes_counters_histograms_and_summaries() {
+        let counter = IntCounterVec::new(
+            Opts::new("app_requests_total", "Requests\\total\nin \"app\""),
+            &["path"],
+        )
+        .unwrap();
+        counter.with_label_values(&["/a\"b\\c\nd"]).inc_by(3);
+
+        let histogram = HistogramVec::new(
+            HistogramOpts::new("app_duration_seconds", "Duration").buckets(vec![0.1, 1.0]),
+            &["endpoint"],
+        )
+        .unwrap();
+        let exemplars = Arc::new(Exemplars::new(
+            "app_duration_seconds".into(),
+            SeriesKeys::new(&HashMap::new(), &["endpoint"]),
+            &[0.1, 1.0],
+            &HashMap::new(),
+        ));
+        for (value, trace_id) in [(0.05, "aaa"), (0.5, "bbb"), (2.0, "ccc")] {
+            histogram.with_label_values(&["/"]).observe(value);
+            exemplars.observe_at("/", &["/"], value, trace_id, 1700000001.5);
+        }
+
+        let summary = SummaryVec::new(
+            Opts::new("app_response_size_bytes", "Size"),
+            &["endpoint"],
+            &SummaryOpts {
+                quantiles: vec![0.5, 0.9],
+                max_age: Duration::from_secs(600),
+                age_buckets: 5,
+            },
+        )
+        .unwrap();
+        for value in [1.0, 2.0, 3.0] {
+            summary.observe(&["/"], value);
+        }
+
+        let metric_families: Vec<MetricFamily> =
+            [counter.collect(), histogram.collect(), summary.collect()].concat();
+        let encoder = OpenMetricsEncoder::new()
+            .created(UNIX_EPOCH + Duration::from_secs(1700000000))
+            .exemplars(exemplars);
+
+        assert_eq!(
+            encode(&encoder, &metric_families),
+            r#"# TYPE app_requests counter
+# HELP app_requests Requests\\total\nin \"app\"
+app_requests_total{path="/a\"b\\c\nd"} 3
+app_requests_created{path="/a\"b\\c\nd"} 1700000000
+# TYPE app_duration_seconds histogram
+# UNIT app_duration_seconds seconds
+# HELP app_duration_seconds Duration
+app_duration_seconds_bucket{endpoint="/",le="0.1"} 1 # {trace_id="aaa"} 0.05 1700000001.5
+app_duration_seconds_bucket{endpoint="/",le="1.0"} 2 # {trace_id="bbb"} 0.5 1700000001.5
+app_duration_seconds_bucket{endpoint="/",le="+Inf"} 3 # {trace_id="ccc"} 2 1700000001.5
+app_duration_seconds_count{endpoint="/"} 3
+app_duration_seconds_sum{endpoint="/"} 2.55
+app_duration_seconds_created{endpoint="/"} 1700000000
+# TYPE app_response_size_bytes summary
+# UNIT app_response_size_bytes bytes
+# HELP app_response_size_bytes Size
+app_response_size_bytes{endpoint="/",quantile="0.5"} 2
+app_response_size_bytes{endpoint="/",quantile="0.9"} 3
+app_response_size_bytes_count{endpoint="/"} 3
+app_response_size_bytes_sum{endpoint="/"} 6
+app_response_size_bytes_created{endpoint="/"} 1700000000
+# EOF
+"#
+        );
+    }
+
+    #[test]
+    fn encodes_the_created_series_of_the_selected_families() {
+        let registry = Registry::new();
+        for name in ["app_requests_total", "user_jobs_total"] {
+            let counter = IntCounterVec::new(Opts::new(name, "Count"), &[]).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter.with_label_values(&[]).inc();
+        }
+        let encoder = OpenMetricsEncoder::new()
+            .created(UNIX_EPOCH + Duration::from_secs(1700000000))
+            .created_families(Arc::new(HashSet::from(["app_requests_total".to_string()])));
+
+        let encoded = encode(&encoder, &registry.gather());
+        assert!(encoded.contains("app_requests_created 1700000000\n"));
+        assert!(encoded.contains("user_jobs_total 1\n"));
+        assert!(!encoded.contains("user_jobs_created"));
+    }
+
+    #[test]
+    fn encodes_empty_registry() {
+        assert_eq!(encode(&OpenMetricsEncoder::new(), &[]), "# EOF\n");
+    }
+}
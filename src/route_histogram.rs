@@ -0,0 +1,58 @@
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::MetricFamily;
+use prometheus::{HistogramOpts, HistogramVec};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A histogram vector with distinct buckets for some routes
+///
+/// Each route with its own buckets is recorded in a separate `HistogramVec`,
+/// all of them being exposed as a single metric family.
+#[derive(Debug, Clone)]
+pub struct RouteHistogramVec {
+    default: HistogramVec,
+    routes: Arc<HashMap<String, HistogramVec>>,
+}
+
+impl RouteHistogramVec {
+    pub(crate) fn new(
+        opts: HistogramOpts,
+        label_names: &[&str],
+        route_buckets: &HashMap<String, Vec<f64>>,
+    ) -> prometheus::Result<Self> {
+        let routes = route_buckets
+            .iter()
+            .map(|(route, buckets)| {
+                let opts = opts.clone().buckets(buckets.clone());
+                Ok((route.clone(), HistogramVec::new(opts, label_names)?))
+            })
+            .collect::<prometheus::Result<_>>()?;
+        Ok(Self {
+            default: HistogramVec::new(opts, label_names)?,
+            routes: Arc::new(routes),
+        })
+    }
+
+    /// Histogram vector recording the observations of this route
+    pub fn with_route(&self, route: &str) -> &HistogramVec {
+        self.routes.get(route).unwrap_or(&self.default)
+    }
+}
+
+impl Collector for RouteHistogramVec {
+    fn desc(&self) -> Vec<&Desc> {
+        self.default.desc()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut metric_families = self.default.collect();
+        if let Some(mf) = metric_families.first_mut() {
+            for route in self.routes.values() {
+                for mut route_mf in route.collect() {
+                    mf.mut_metric().extend(route_mf.take_metric());
+                }
+            }
+        }
+        metric_families
+    }
+}
@@ -0,0 +1,48 @@
+use prometheus::proto::LabelPair;
+use std::collections::HashMap;
+
+/// Label pairs identifying a series, sorted by name
+pub(crate) type SeriesLabels = Vec<(String, String)>;
+
+/// Builds the `SeriesLabels` of the series of a metric vector, to match them
+/// with the gathered metrics
+#[derive(Debug, Clone)]
+pub(crate) struct SeriesKeys {
+    const_labels: SeriesLabels,
+    label_names: Vec<String>,
+}
+
+impl SeriesKeys {
+    pub(crate) fn new(const_labels: &HashMap<String, String>, label_names: &[&str]) -> Self {
+        Self {
+            const_labels: const_labels
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+            label_names: label_names.iter().map(|name| name.to_string()).collect(),
+        }
+    }
+
+    /// Labels of the series with these label values, in the order of the label names
+    pub(crate) fn key(&self, label_values: &[&str]) -> SeriesLabels {
+        let mut labels: SeriesLabels = self
+            .label_names
+            .iter()
+            .zip(label_values)
+            .map(|(name, value)| (name.clone(), value.to_string()))
+            .chain(self.const_labels.iter().cloned())
+            .collect();
+        labels.sort();
+        labels
+    }
+
+    /// Labels of a gathered metric
+    pub(crate) fn metric_key(labels: &[LabelPair]) -> SeriesLabels {
+        let mut labels: SeriesLabels = labels
+            .iter()
+            .map(|label| (label.get_name().to_string(), label.get_value().to_string()))
+            .collect();
+        labels.sort();
+        labels
+    }
+}
@@ -0,0 +1,413 @@
+use crate::route_histogram::RouteHistogramVec;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::{LabelPair, Metric, MetricFamily, MetricType, Quantile, Summary};
+use prometheus::Opts;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Default quantiles of the request duration summary
+pub const DEFAULT_SUMMARY_QUANTILES: &[f64; 3] = &[0.5, 0.9, 0.99];
+
+/// Default sliding window of the request duration summary
+pub const DEFAULT_SUMMARY_MAX_AGE: Duration = Duration::from_secs(600);
+
+/// Default number of age buckets of the request duration summary
+pub const DEFAULT_SUMMARY_AGE_BUCKETS: u32 = 5;
+
+/// Configuration of the request duration summary
+#[derive(Debug, Clone)]
+pub(crate) struct SummaryOpts {
+    pub(crate) quantiles: Vec<f64>,
+    pub(crate) max_age: Duration,
+    pub(crate) age_buckets: u32,
+}
+
+/// Samples kept per age bucket: above, the samples are randomly replaced (reservoir sampling)
+const MAX_SAMPLES_PER_AGE_BUCKET: usize = 1024;
+
+/// A summary vector computing its quantiles over a sliding time window
+///
+/// The window of `max_age` is split in `age_buckets` buckets: every `max_age / age_buckets`,
+/// the observations of the oldest bucket are discarded.
+#[derive(Debug, Clone)]
+pub struct SummaryVec {
+    desc: Desc,
+    quantiles: Vec<f64>,
+    age_bucket_duration: Duration,
+    age_buckets: usize,
+    series: Arc<Mutex<HashMap<Vec<String>, SummarySeries>>>,
+}
+
+impl SummaryVec {
+    /// Returns an error if a quantile is not in [0, 1], or if the age buckets last no time
+    pub(crate) fn new(
+        opts: Opts,
+        label_names: &[&str],
+        summary_opts: &SummaryOpts,
+    ) -> prometheus::Result<Self> {
+        if let Some(q) = summary_opts
+            .quantiles
+            .iter()
+            .find(|q| !(0.0..=1.0).contains(*q))
+        {
+            return Err(prometheus::Error::Msg(format!(
+                "summary quantile {q} must be in [0, 1]"
+            )));
+        }
+        let age_buckets = summary_opts.age_buckets.max(1);
+        if (summary_opts.max_age / age_buckets).is_zero() {
+            return Err(prometheus::Error::Msg(format!(
+                "summary max age {:?} is too short for {age_buckets} age buckets",
+                summary_opts.max_age
+            )));
+        }
+        let desc = Desc::new(
+            opts.fq_name(),
+            opts.help,
+            label_names.iter().map(|name| name.to_string()).collect(),
+            opts.const_labels,
+        )?;
+        Ok(Self {
+            desc,
+            quantiles: summary_opts.quantiles.clone(),
+            age_bucket_duration: summary_opts.max_age / age_buckets,
+            age_buckets: age_buckets as usize,
+            series: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// Observe a value in the series with these label values
+    pub fn observe(&self, label_values: &[&str], value: f64) {
+        let key = label_values.iter().map(|value| value.to_string()).collect();
+        let mut series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        series
+            .entry(key)
+            .or_insert_with(|| SummarySeries::new(self.age_buckets, Instant::now()))
+            .observe(value, self.age_bucket_duration, Instant::now());
+    }
+}
+
+impl Collector for SummaryVec {
+    fn desc(&self) -> Vec<&Desc> {
+        vec![&self.desc]
+    }
+
+    // the `prometheus` proto model uses `Vec` or `RepeatedField` depending on its `protobuf` feature
+    #[allow(clippy::useless_conversion)]
+    fn collect(&self) -> Vec<MetricFamily> {
+        let mut series = self.series.lock().unwrap_or_else(PoisonError::into_inner);
+        let metrics: Vec<Metric> = series
+            .iter_mut()
+            .map(|(label_values, summary_series)| {
+                let mut labels: Vec<LabelPair> = self
+                    .desc
+                    .variable_labels
+                    .iter()
+                    .zip(label_values)
+                    .map(|(name, value)| {
+                        let mut label = LabelPair::default();
+                        label.set_name(name.clone());
+                        label.set_value(value.clone());
+                        label
+                    })
+                    .chain(self.desc.const_label_pairs.iter().cloned())
+                    .collect();
+                labels.sort_by(|a, b| a.get_name().cmp(b.get_name()));
+
+                let mut summary = Summary::default();
+                summary.set_sample_count(summary_series.count);
+                summary.set_sample_sum(summary_series.sum);
+                let quantiles: Vec<Quantile> = summary_series
+                    .quantiles(&self.quantiles, self.age_bucket_duration, Instant::now())
+                    .into_iter()
+                    .map(|(q, value)| {
+                        let mut quantile = Quantile::default();
+                        quantile.set_quantile(q);
+                        quantile.set_value(value);
+                        quantile
+                    })
+                    .collect();
+                summary.set_quantile(quantiles.into());
+
+                let mut metric = Metric::default();
+                metric.set_label(labels.into());
+                metric.set_summary(summary);
+                metric
+            })
+            .collect();
+
+        let mut mf = MetricFamily::default();
+        mf.set_name(self.desc.fq_name.clone());
+        mf.set_help(self.desc.help.clone());
+        mf.set_field_type(MetricType::SUMMARY);
+        mf.set_metric(metrics.into());
+        vec![mf]
+    }
+}
+
+/// Request duration metric, recorded as a histogram (default) or as a summary
+#[derive(Debug, Clone)]
+pub enum DurationMetric {
+    Histogram(RouteHistogramVec),
+    Summary(SummaryVec),
+}
+
+impl DurationMetric {
+    /// Observe a value of this route in the series with these label values
+    pub fn observe(&self, route: &str, label_values: &[&str], value: f64) {
+        match self {
+            DurationMetric::Histogram(histogram) => histogram
+                .with_route(route)
+                .with_label_values(label_values)
+                .observe(value),
+            DurationMetric::Summary(summary) => summary.observe(label_values, value),
+        }
+    }
+}
+
+impl Collector for DurationMetric {
+    fn desc(&self) -> Vec<&Desc> {
+        match self {
+            DurationMetric::Histogram(histogram) => histogram.desc(),
+            DurationMetric::Summary(summary) => summary.desc(),
+        }
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        match self {
+            DurationMetric::Histogram(histogram) => histogram.collect(),
+            DurationMetric::Summary(summary) => summary.collect(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct AgeBucket {
+    count: u64,
+    samples: Vec<f64>,
+}
+
+impl AgeBucket {
+    fn clear(&mut self) {
+        self.count = 0;
+        self.samples.clear();
+    }
+}
+
+#[derive(Debug)]
+struct SummarySeries {
+    count: u64,
+    sum: f64,
+    age_buckets: Vec<AgeBucket>,
+    /// bucket receiving the observations
+    head: usize,
+    head_started: Instant,
+    /// xorshift state for the reservoir sampling
+    rng: u64,
+}
+
+impl SummarySeries {
+    fn new(age_buckets: usize, now: Instant) -> Self {
+        Self {
+            count: 0,
+            sum: 0.0,
+            age_buckets: (0..age_buckets).map(|_| AgeBucket::default()).collect(),
+            head: 0,
+            head_started: now,
+            rng: 0x9E37_79B9_7F4A_7C15,
+        }
+    }
+
+    /// Discard the age buckets older than the window
+    fn rotate(&mut self, age_bucket_duration: Duration, now: Instant) {
+        let max_age = age_bucket_duration * self.age_buckets.len() as u32;
+        if now.duration_since(self.head_started) >= max_age {
+            self.age_buckets.iter_mut().for_each(AgeBucket::clear);
+            self.head_started = now;
+            return;
+        }
+        while now.duration_since(self.head_started) >= age_bucket_duration {
+            self.head = (self.head + 1) % self.age_buckets.len();
+            self.age_buckets[self.head].clear();
+            self.head_started += age_bucket_duration;
+        }
+    }
+
+    fn observe(&mut self, value: f64, age_bucket_duration: Duration, now: Instant) {
+        self.rotate(age_bucket_duration, now);
+        self.count += 1;
+        self.sum += value;
+
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        let random = self.rng;
+
+        let bucket = &mut self.age_buckets[self.head];
+        bucket.count += 1;
+        if bucket.samples.len() < MAX_SAMPLES_PER_AGE_BUCKET {
+            bucket.samples.push(value);
+        } else {
+            let index = (random % bucket.count) as usize;
+            if index < MAX_SAMPLES_PER_AGE_BUCKET {
+                bucket.samples[index] = value;
+            }
+        }
+    }
+
+    /// Quantiles of the observations in the window, `NaN` if there is none
+    fn quantiles(
+        &mut self,
+        quantiles: &[f64],
+        age_bucket_duration: Duration,
+        now: Instant,
+    ) -> Vec<(f64, f64)> {
+        self.rotate(age_bucket_duration, now);
+
+        // each sample stands for `count / samples` observations of its bucket
+        let mut samples: Vec<(f64, f64)> = self
+            .age_buckets
+            .iter()
+            .filter(|bucket| !bucket.samples.is_empty())
+            .flat_map(|bucket| {
+                let weight = bucket.count as f64 / bucket.samples.len() as f64;
+                bucket.samples.iter().map(move |value| (*value, weight))
+            })
+            .collect();
+        samples.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let total_weight: f64 = samples.iter().map(|(_, weight)| weight).sum();
+
+        quantiles
+            .iter()
+            .map(|q| {
+                let rank = q * total_weight;
+                let mut cumulated = 0.0;
+                let value = samples
+                    .iter()
+                    .find(|(_, weight)| {
+                        cumulated += weight;
+                        cumulated >= rank
+                    })
+                    .or(samples.last())
+                    .map_or(f64::NAN, |(value, _)| *value);
+                (*q, value)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AGE_BUCKET: Duration = Duration::from_secs(10);
+
+    fn summary_opts(quantiles: &[f64], max_age: Duration) -> SummaryOpts {
+        SummaryOpts {
+            quantiles: quantiles.to_vec(),
+            max_age,
+            age_buckets: 2,
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_options() {
+        let new = |summary_opts: SummaryOpts| {
+            SummaryVec::new(Opts::new("duration", "help"), &[], &summary_opts)
+        };
+        assert!(new(summary_opts(&[0.0, 0.5, 1.0], AGE_BUCKET)).is_ok());
+        assert!(new(summary_opts(&[1.5], AGE_BUCKET)).is_err());
+        assert!(new(summary_opts(&[-0.1], AGE_BUCKET)).is_err());
+        assert!(new(summary_opts(&[f64::NAN], AGE_BUCKET)).is_err());
+        assert!(new(summary_opts(&[0.5], Duration::ZERO)).is_err());
+        assert!(new(summary_opts(&[0.5], Duration::from_nanos(1))).is_err());
+    }
+
+    #[test]
+    fn computes_the_quantiles_by_rank() {
+        let start = Instant::now();
+        let mut series = SummarySeries::new(2, start);
+        for value in 1..=10 {
+            series.observe(f64::from(value), AGE_BUCKET, start);
+        }
+        assert_eq!(
+            series.quantiles(&[0.0, 0.5, 0.9, 1.0], AGE_BUCKET, start),
+            [(0.0, 1.0), (0.5, 5.0), (0.9, 9.0), (1.0, 10.0)]
+        );
+        assert_eq!((series.count, series.sum), (10, 55.0));
+    }
+
+    #[test]
+    fn discards_the_oldest_age_bucket() {
+        let start = Instant::now();
+        let mut series = SummarySeries::new(2, start);
+        series.observe(1.0, AGE_BUCKET, start);
+        series.observe(2.0, AGE_BUCKET, start + AGE_BUCKET);
+        assert_eq!(
+            series.quantiles(&[0.0, 1.0], AGE_BUCKET, start + AGE_BUCKET),
+            [(0.0, 1.0), (1.0, 2.0)]
+        );
+
+        // the bucket of the first observation is reused
+        let now = start + AGE_BUCKET * 2;
+        assert_eq!(
+            series.quantiles(&[0.0, 1.0], AGE_BUCKET, now),
+            [(0.0, 2.0), (1.0, 2.0)]
+        );
+        // the count and the sum are never reset
+        assert_eq!((series.count, series.sum), (2, 3.0));
+    }
+
+    #[test]
+    fn discards_the_whole_window() {
+        let start = Instant::now();
+        let mut series = SummarySeries::new(2, start);
+        series.observe(1.0, AGE_BUCKET, start);
+        series.observe(2.0, AGE_BUCKET, start + AGE_BUCKET / 2);
+
+        let quantiles = series.quantiles(&[0.5], AGE_BUCKET, start + AGE_BUCKET * 5);
+        assert!(quantiles[0].1.is_nan());
+
+        series.observe(3.0, AGE_BUCKET, start + AGE_BUCKET * 5);
+        assert_eq!(
+            series.quantiles(&[0.5], AGE_BUCKET, start + AGE_BUCKET * 5),
+            [(0.5, 3.0)]
+        );
+    }
+
+    #[test]
+    fn samples_the_observations_above_the_reservoir() {
+        let start = Instant::now();
+        let mut series = SummarySeries::new(2, start);
+        for value in 0..10_000 {
+            series.observe(f64::from(value), AGE_BUCKET, start);
+        }
+        assert_eq!(series.age_buckets[0].count, 10_000);
+        assert_eq!(
+            series.age_buckets[0].samples.len(),
+            MAX_SAMPLES_PER_AGE_BUCKET
+        );
+        // the reservoir is a uniform sample: the median stays around 5000
+        let median = series.quantiles(&[0.5], AGE_BUCKET, start)[0].1;
+        assert!((4000.0..6000.0).contains(&median), "{median}");
+    }
+
+    #[test]
+    fn weights_the_samples_by_their_age_bucket_count() {
+        let start = Instant::now();
+        let mut series = SummarySeries::new(2, start);
+        for _ in 0..10_000 {
+            series.observe(1.0, AGE_BUCKET, start);
+        }
+        for _ in 0..100 {
+            series.observe(2.0, AGE_BUCKET, start + AGE_BUCKET);
+        }
+        // unweighted, 2 would be above the 0.91 quantile, with 100 samples out of 1124
+        let now = start + AGE_BUCKET;
+        assert_eq!(
+            series.quantiles(&[0.95, 0.995], AGE_BUCKET, now),
+            [(0.95, 1.0), (0.995, 2.0)]
+        );
+    }
+}
@@ -0,0 +1,143 @@
+use axum::body::Body;
+use axum::Router;
+use axum_prom::PrometheusMetricsBuilder;
+use http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+use http::{Request, StatusCode};
+use tower::ServiceExt;
+
+fn app(builder: PrometheusMetricsBuilder) -> Router {
+    let (prometheus_metrics, registry) = builder.pair().unwrap();
+    registry.router().layer(prometheus_metrics)
+}
+
+async fn scrape(app: &Router, authorization: Option<&str>) -> (StatusCode, Option<String>) {
+    let mut request = Request::get("/metrics");
+    if let Some(authorization) = authorization {
+        request = request.header(AUTHORIZATION, authorization);
+    }
+    let response = app
+        .clone()
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let challenge = response
+        .headers()
+        .get(WWW_AUTHENTICATE)
+        .map(|value| value.to_str().unwrap().to_string());
+    (response.status(), challenge)
+}
+
+// "prometheus:secret"
+const CREDENTIALS: &str = "cHJvbWV0aGV1czpzZWNyZXQ=";
+// "prometheus:wrong"
+const WRONG_CREDENTIALS: &str = "cHJvbWV0aGV1czp3cm9uZw==";
+
+#[tokio::test]
+async fn basic_auth_challenges_missing_credentials() {
+    let app = app(PrometheusMetricsBuilder::new("test").basic_auth("prometheus", "secret"));
+    let challenge = Some("Basic realm=\"metrics\"".to_string());
+
+    assert_eq!(
+        scrape(&app, None).await,
+        (StatusCode::UNAUTHORIZED, challenge.clone())
+    );
+    // another scheme is like no credentials
+    assert_eq!(
+        scrape(&app, Some("Bearer secret")).await,
+        (StatusCode::UNAUTHORIZED, challenge)
+    );
+}
+
+#[tokio::test]
+async fn basic_auth_forbids_wrong_credentials() {
+    let app = app(PrometheusMetricsBuilder::new("test").basic_auth("prometheus", "secret"));
+
+    let authorization = format!("Basic {WRONG_CREDENTIALS}");
+    assert_eq!(
+        scrape(&app, Some(&authorization)).await,
+        (StatusCode::FORBIDDEN, None)
+    );
+}
+
+#[tokio::test]
+async fn basic_auth_accepts_the_credentials() {
+    let app = app(PrometheusMetricsBuilder::new("test").basic_auth("prometheus", "secret"));
+
+    let authorization = format!("Basic {CREDENTIALS}");
+    assert_eq!(
+        scrape(&app, Some(&authorization)).await,
+        (StatusCode::OK, None)
+    );
+    // the scheme is case-insensitive
+    let authorization = format!("basic {CREDENTIALS}");
+    assert_eq!(
+        scrape(&app, Some(&authorization)).await,
+        (StatusCode::OK, None)
+    );
+}
+
+#[tokio::test]
+async fn bearer_token_challenges_missing_token() {
+    let app = app(PrometheusMetricsBuilder::new("test").bearer_token("secret"));
+
+    assert_eq!(
+        scrape(&app, None).await,
+        (StatusCode::UNAUTHORIZED, Some("Bearer".to_string()))
+    );
+}
+
+#[tokio::test]
+async fn bearer_token_forbids_wrong_token() {
+    let app = app(PrometheusMetricsBuilder::new("test").bearer_token("secret"));
+
+    assert_eq!(
+        scrape(&app, Some("Bearer wrong")).await,
+        (StatusCode::FORBIDDEN, None)
+    );
+    assert_eq!(
+        scrape(&app, Some("Bearer secret2")).await,
+        (StatusCode::FORBIDDEN, None)
+    );
+}
+
+#[tokio::test]
+async fn bearer_token_accepts_the_token() {
+    let app = app(PrometheusMetricsBuilder::new("test").bearer_token("secret"));
+
+    assert_eq!(
+        scrape(&app, Some("Bearer secret")).await,
+        (StatusCode::OK, None)
+    );
+    assert_eq!(
+        scrape(&app, Some("BEARER secret")).await,
+        (StatusCode::OK, None)
+    );
+}
+
+#[tokio::test]
+async fn predicate_authorizes_the_requests() {
+    let app = app(
+        PrometheusMetricsBuilder::new("test").authorize(|parts| async move {
+            // the future can hold the request parts
+            tokio::task::yield_now().await;
+            parts
+                .headers
+                .get("x-api-key")
+                .is_some_and(|key| key == "secret")
+        }),
+    );
+
+    let scrape = |api_key: &'static str| {
+        app.clone().oneshot(
+            Request::get("/metrics")
+                .header("x-api-key", api_key)
+                .body(Body::empty())
+                .unwrap(),
+        )
+    };
+    assert_eq!(scrape("secret").await.unwrap().status(), StatusCode::OK);
+    assert_eq!(
+        scrape("wrong").await.unwrap().status(),
+        StatusCode::FORBIDDEN
+    );
+}
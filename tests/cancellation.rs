@@ -0,0 +1,103 @@
+use axum::body::Body;
+use axum::routing::get;
+use axum::Router;
+use axum_prom::{PrometheusMetricsBuilder, PrometheusMetricsRegistry, RequestBody};
+use http::{Request, Response};
+use std::time::Duration;
+use tower::{service_fn, Layer, ServiceExt};
+
+const DELAY: Duration = Duration::from_millis(50);
+
+/// Value of the series of `metric` whose labels contain all of `labels`
+fn value(registry: &PrometheusMetricsRegistry, metric: &str, labels: &[&str]) -> f64 {
+    let metrics = registry.metrics();
+    let line = metrics
+        .lines()
+        .find(|line| {
+            line.starts_with(&format!("{metric}{{"))
+                && labels.iter().all(|label| line.contains(label))
+        })
+        .unwrap_or_else(|| panic!("no {metric} series with {labels:?} in\n{metrics}"));
+    line.rsplit(' ').next().unwrap().parse().unwrap()
+}
+
+async fn drop_before_completion(builder: PrometheusMetricsBuilder) -> PrometheusMetricsRegistry {
+    let (prometheus_metrics, registry) = builder.pair().unwrap();
+    let app = Router::new()
+        .route("/slow", get(std::future::pending::<&'static str>))
+        .layer(prometheus_metrics);
+
+    let request = Request::get("/slow").body(Body::empty()).unwrap();
+    let response = tokio::time::timeout(DELAY, app.oneshot(request)).await;
+    assert!(response.is_err(), "the request should time out");
+    registry
+}
+
+#[tokio::test]
+async fn dropped_request_is_recorded_as_cancelled() {
+    let registry = drop_before_completion(PrometheusMetricsBuilder::new("test")).await;
+
+    let labels = ["endpoint=\"/slow\"", "status=\"cancelled\""];
+    assert_eq!(value(&registry, "test_http_requests_total", &labels), 1.0);
+    assert_eq!(
+        value(
+            &registry,
+            "test_http_requests_duration_seconds_count",
+            &labels
+        ),
+        1.0
+    );
+    let duration = value(
+        &registry,
+        "test_http_requests_duration_seconds_sum",
+        &labels,
+    );
+    assert!(duration >= DELAY.as_secs_f64(), "{duration}");
+    assert_eq!(
+        value(
+            &registry,
+            "test_http_requests_in_flight",
+            &["endpoint=\"/slow\""]
+        ),
+        0.0
+    );
+}
+
+#[tokio::test]
+async fn dropped_request_is_recorded_with_the_cancelled_status() {
+    let registry =
+        drop_before_completion(PrometheusMetricsBuilder::new("test").cancelled_status("499")).await;
+
+    let labels = ["endpoint=\"/slow\"", "status=\"499\""];
+    assert_eq!(value(&registry, "test_http_requests_total", &labels), 1.0);
+}
+
+#[tokio::test]
+async fn service_error_is_recorded_as_error() {
+    let (prometheus_metrics, registry) = PrometheusMetricsBuilder::new("test").pair().unwrap();
+    let service = prometheus_metrics.layer(service_fn(|_: Request<RequestBody<Body>>| async {
+        Err::<Response<Body>, _>("unavailable")
+    }));
+
+    let request = Request::get("/failing").body(Body::empty()).unwrap();
+    assert_eq!(service.oneshot(request).await.unwrap_err(), "unavailable");
+
+    let labels = ["endpoint=\"<unmatched>\"", "status=\"error\""];
+    assert_eq!(value(&registry, "test_http_requests_total", &labels), 1.0);
+    assert_eq!(
+        value(
+            &registry,
+            "test_http_requests_duration_seconds_count",
+            &labels
+        ),
+        1.0
+    );
+    assert_eq!(
+        value(
+            &registry,
+            "test_http_requests_in_flight",
+            &["endpoint=\"<unmatched>\""]
+        ),
+        0.0
+    );
+}
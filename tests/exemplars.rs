@@ -0,0 +1,97 @@
+use axum::body::Body;
+use axum::routing::get;
+use axum::Router;
+use axum_prom::{PrometheusMetricsBuilder, OPENMETRICS_FORMAT};
+use http::header::ACCEPT;
+use http::Request;
+use tower::ServiceExt;
+
+const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+async fn scrape_openmetrics(app: Router) -> String {
+    let response = app
+        .oneshot(
+            Request::get("/metrics")
+                .header(ACCEPT, OPENMETRICS_FORMAT)
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+async fn get_hello(app: Router, header: Option<(&str, &str)>) {
+    let mut request = Request::get("/hello");
+    if let Some((name, value)) = header {
+        request = request.header(name, value);
+    }
+    let response = app
+        .oneshot(request.body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+}
+
+fn app(builder: PrometheusMetricsBuilder) -> Router {
+    let (prometheus_metrics, registry) = builder.pair().unwrap();
+    Router::new()
+        .route("/hello", get(|| async { "Hello, World!" }))
+        .merge(registry.router())
+        .layer(prometheus_metrics)
+}
+
+fn exemplar_lines(metrics: &str) -> Vec<&str> {
+    metrics
+        .lines()
+        .filter(|line| line.starts_with("test_http_requests_duration_seconds_bucket{"))
+        .filter(|line| line.contains(" # {"))
+        .collect()
+}
+
+#[tokio::test]
+async fn attaches_the_traceparent_trace_id() {
+    let app = app(PrometheusMetricsBuilder::new("test").exemplars(true));
+    get_hello(app.clone(), Some(("traceparent", TRACEPARENT))).await;
+
+    let metrics = scrape_openmetrics(app).await;
+    let lines = exemplar_lines(&metrics);
+    assert_eq!(lines.len(), 1, "{metrics}");
+    assert!(lines[0].contains("endpoint=\"/hello\""));
+    assert!(lines[0].contains("# {trace_id=\"4bf92f3577b34da6a3ce929d0e0e4736\"}"));
+}
+
+#[tokio::test]
+async fn attaches_no_exemplar_without_trace_id() {
+    let app = app(PrometheusMetricsBuilder::new("test").exemplars(true));
+    get_hello(app.clone(), None).await;
+    let traceparent = "00-00000000000000000000000000000000-00f067aa0ba902b7-01";
+    get_hello(app.clone(), Some(("traceparent", traceparent))).await;
+
+    let metrics = scrape_openmetrics(app).await;
+    assert!(exemplar_lines(&metrics).is_empty(), "{metrics}");
+}
+
+#[tokio::test]
+async fn attaches_the_extracted_trace_id() {
+    let app = app(
+        PrometheusMetricsBuilder::new("test").exemplar_extractor(|parts| {
+            parts
+                .headers
+                .get("x-request-id")
+                .and_then(|value| value.to_str().ok())
+                .map(String::from)
+        }),
+    );
+    get_hello(app.clone(), Some(("x-request-id", "req-42"))).await;
+
+    let metrics = scrape_openmetrics(app).await;
+    let lines = exemplar_lines(&metrics);
+    assert_eq!(lines.len(), 1, "{metrics}");
+    assert!(lines[0].contains("# {trace_id=\"req-42\"}"));
+}
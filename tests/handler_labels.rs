@@ -0,0 +1,51 @@
+use axum::body::Body;
+use axum::routing::get;
+use axum::{Extension, Router};
+use axum_prom::{MetricLabels, PrometheusMetricsBuilder};
+use http::Request;
+use tower::ServiceExt;
+
+#[tokio::test]
+async fn records_the_labels_set_by_the_handler() {
+    let (prometheus_metrics, registry) = PrometheusMetricsBuilder::new("test")
+        .handler_labels(&["operation"])
+        .pair()
+        .unwrap();
+    let app = Router::new()
+        .route(
+            "/checkout",
+            get(|| async {
+                let labels = MetricLabels::new()
+                    .with("operation", "checkout")
+                    .with("undeclared", "ignored");
+                (Extension(labels), "done")
+            }),
+        )
+        .route("/hello", get(|| async { "Hello, World!" }))
+        .layer(prometheus_metrics);
+
+    for path in ["/checkout", "/hello"] {
+        let response = app
+            .clone()
+            .oneshot(Request::get(path).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+    }
+
+    let metrics = registry.metrics();
+    let total: Vec<&str> = metrics
+        .lines()
+        .filter(|line| line.starts_with("test_http_requests_total{"))
+        .collect();
+    assert_eq!(
+        total,
+        [
+            "test_http_requests_total{endpoint=\"/checkout\",method=\"GET\",operation=\"checkout\",status=\"200\"} 1",
+            "test_http_requests_total{endpoint=\"/hello\",method=\"GET\",operation=\"\",status=\"200\"} 1",
+        ]
+    );
+    assert!(!metrics.contains("undeclared"));
+}
@@ -0,0 +1,91 @@
+use axum::body::Body;
+use axum::routing::get;
+use axum::Router;
+use axum_prom::{PrometheusMetricsBuilder, PrometheusMetricsRegistry};
+use http::{Method, Request, StatusCode};
+use tower::ServiceExt;
+
+fn app() -> (Router, PrometheusMetricsRegistry) {
+    let (prometheus_metrics, registry) = PrometheusMetricsBuilder::new("test").pair().unwrap();
+    let app = Router::new()
+        .route("/hello", get(|| async { "Hello, World!" }))
+        .route("/empty", get(|| async { StatusCode::NO_CONTENT }))
+        .layer(prometheus_metrics);
+    (app, registry)
+}
+
+fn body_outcome(registry: &PrometheusMetricsRegistry, method: &str, status: &str) -> String {
+    let prefix = "test_http_response_body_duration_seconds_count{";
+    let line = registry
+        .metrics()
+        .lines()
+        .find(|line| {
+            line.starts_with(prefix)
+                && line.contains(&format!("method=\"{method}\""))
+                && line.contains(&format!("status=\"{status}\""))
+        })
+        .unwrap_or_else(|| panic!("no response body series for {method} {status}"))
+        .to_string();
+    let outcome = line.split("outcome=\"").nth(1).unwrap();
+    outcome[..outcome.find('"').unwrap()].to_string()
+}
+
+// like hyper, the tests never poll a body already at its end or the body of a HEAD response
+
+#[tokio::test]
+async fn no_content_response_is_completed() {
+    let (app, registry) = app();
+    let response = app
+        .oneshot(Request::get("/empty").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    drop(response);
+
+    assert_eq!(body_outcome(&registry, "GET", "204"), "completed");
+}
+
+#[tokio::test]
+async fn head_response_is_completed() {
+    let (app, registry) = app();
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(Method::HEAD)
+                .uri("/hello")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    drop(response);
+
+    assert_eq!(body_outcome(&registry, "HEAD", "200"), "completed");
+}
+
+#[tokio::test]
+async fn fully_sent_response_is_completed() {
+    let (app, registry) = app();
+    let response = app
+        .oneshot(Request::get("/hello").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap();
+
+    assert_eq!(body_outcome(&registry, "GET", "200"), "completed");
+}
+
+#[tokio::test]
+async fn unsent_response_is_dropped() {
+    let (app, registry) = app();
+    let response = app
+        .oneshot(Request::get("/hello").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    drop(response);
+
+    assert_eq!(body_outcome(&registry, "GET", "200"), "dropped");
+}
@@ -0,0 +1,48 @@
+use axum_prom::PrometheusMetricsBuilder;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+
+#[tokio::test]
+async fn serves_the_metrics_until_shutdown() {
+    let (_, registry) = PrometheusMetricsBuilder::new("test").pair().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (shutdown, signal) = oneshot::channel::<()>();
+    let server = tokio::spawn(registry.serve_listener_with_graceful_shutdown(
+        listener,
+        async move {
+            let _ = signal.await;
+        },
+    ));
+
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+    assert!(
+        response.contains("content-type: text/plain; version=0.0.4"),
+        "{response}"
+    );
+
+    shutdown.send(()).unwrap();
+    let result = tokio::time::timeout(Duration::from_secs(5), server)
+        .await
+        .expect("the server should shut down")
+        .unwrap();
+    assert!(result.is_ok());
+}
+
+#[tokio::test]
+async fn reports_a_bind_failure() {
+    let (_, registry) = PrometheusMetricsBuilder::new("test").pair().unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+
+    let result = registry.serve(listener.local_addr().unwrap()).await;
+    assert!(result.is_err());
+}